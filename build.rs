@@ -8,7 +8,10 @@ use qpm_cli::{
 };
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    prost_build::compile_protos(&["src/items.proto", "src/packets.proto"], &["src/"])?;
+    // host builds leave the game out, see lib.rs
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("android") {
+        return Ok(());
+    }
 
     let manifest_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
 
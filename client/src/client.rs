@@ -0,0 +1,528 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{SinkExt, Stream, StreamExt};
+use party_panel_protocol::{
+    auth,
+    capabilities::{
+        COMPRESSION_CAPABILITY, HEARTBEAT_CAPABILITY, SONG_LIST_DELTAS_CAPABILITY,
+        SONG_LIST_PAGES_CAPABILITY,
+    },
+    codec::{Encoding, Framing, PartyPanelCodec},
+    error::ProtocolError,
+    heartbeat::{self, Heartbeat},
+    proto::{
+        items::PreviewBeatmapLevel,
+        packets::{
+            Ack, Authenticate, Command, ErrorEvent, Hello, NowPlaying, NowPlayingUpdate, PlaySong,
+            SyncSongs, Welcome,
+        },
+        v2, CommandType, Packet, PacketType, PROTOCOL_VERSION,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{broadcast, mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_util::codec::Framed;
+use tracing::warn;
+
+use crate::{
+    error::ClientError,
+    library::{Applied, Library},
+};
+
+/// How long the mod has to answer `Hello` and `Authenticate`
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events kept for a slow subscriber before it misses some
+const EVENT_QUEUE_SIZE: usize = 256;
+
+/// Packets the client understands or sends, advertised in `Hello`
+pub const SUPPORTED_PACKETS: &[PacketType] = &[
+    PacketType::SongList,
+    PacketType::Command,
+    PacketType::NowPlaying,
+    PacketType::NowPlayingUpdate,
+    PacketType::PlaySong,
+    PacketType::Hello,
+    PacketType::Welcome,
+    PacketType::Ack,
+    PacketType::ErrorEvent,
+    PacketType::Authenticate,
+    PacketType::SongListBegin,
+    PacketType::SongListPage,
+    PacketType::SongListEnd,
+    PacketType::SongsAdded,
+    PacketType::SongsRemoved,
+    PacketType::SongsChanged,
+    PacketType::SyncSongs,
+    PacketType::PlaySongV2,
+];
+
+/// Optional features the client supports, advertised in `Hello`
+pub const CAPABILITIES: &[&str] = &[
+    COMPRESSION_CAPABILITY,
+    SONG_LIST_PAGES_CAPABILITY,
+    SONG_LIST_DELTAS_CAPABILITY,
+    HEARTBEAT_CAPABILITY,
+];
+
+/// What to answer the mod's auth challenge with
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    /// The mod's shared `auth_token`
+    Token(String),
+    /// A device paired with a PIN
+    Device { id: String, secret: String },
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Shown in the mod's logs
+    pub name: String,
+    /// Needed if the mod has an auth token or paired devices
+    pub credentials: Option<Credentials>,
+    /// How long to wait for the mod to `Ack` a request
+    pub request_timeout: Duration,
+    pub heartbeat_interval: Duration,
+    /// How long the mod can stay silent before the connection counts as dead
+    pub heartbeat_timeout: Duration,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            name: "party_panel_client".to_string(),
+            credentials: None,
+            request_timeout: Duration::from_secs(10),
+            heartbeat_interval: Duration::from_secs(5),
+            heartbeat_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Something the mod pushed without being asked
+#[derive(Clone, Debug)]
+pub enum Event {
+    NowPlaying(NowPlaying),
+    NowPlayingUpdate(NowPlayingUpdate),
+    /// The song library changed, [`PartyPanelClient::songs`] has the new one
+    SongsChanged {
+        revision: u64,
+    },
+    Error(ErrorEvent),
+    /// The connection is gone, nothing else follows
+    Disconnected,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SongsState {
+    Loading,
+    Loaded,
+    Closed,
+}
+
+/// State the reading task keeps up to date for the client
+struct Shared {
+    pending: Mutex<HashMap<u32, oneshot::Sender<Ack>>>,
+    library: Mutex<Library>,
+    songs: watch::Sender<SongsState>,
+    now_playing: Mutex<Option<NowPlaying>>,
+    round_trip: Mutex<Option<Duration>>,
+    events: broadcast::Sender<Event>,
+}
+
+/// A connection to the mod, from the panel's side.
+///
+/// Speaks protocol version 2, so it needs a mod with the packet envelope.
+/// Song lists, now playing and heartbeats are handled in the background for
+/// as long as the client is alive; dropping it closes the connection.
+pub struct PartyPanelClient {
+    welcome: Welcome,
+    request_timeout: Duration,
+    outbound: mpsc::UnboundedSender<Packet>,
+    shared: Arc<Shared>,
+    next_request_id: AtomicU32,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Drop for PartyPanelClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn rejected(ack: Ack) -> ClientError {
+    ClientError::Rejected {
+        code: ack.error_code(),
+        message: ack.message,
+    }
+}
+
+/// The next packet during the handshake
+async fn next<S>(
+    framed: &mut Framed<S, PartyPanelCodec>,
+    expected: &'static str,
+) -> Result<Packet, ClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, framed.next())
+        .await
+        .map_err(|_| ClientError::Timeout(expected))?
+        .ok_or(ClientError::Closed)?
+        .map_err(ClientError::from)
+}
+
+async fn authenticate<S>(
+    framed: &mut Framed<S, PartyPanelCodec>,
+    credentials: Option<&Credentials>,
+    challenge: &[u8],
+) -> Result<(), ClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (secret, device_id) = match credentials {
+        Some(Credentials::Token(token)) => (token, String::new()),
+        Some(Credentials::Device { id, secret }) => (secret, id.clone()),
+        None => return Err(ClientError::AuthRequired),
+    };
+
+    let authenticate = Authenticate {
+        response: auth::response(secret, challenge),
+        request_id: 1,
+        device_id,
+    };
+    framed.send(authenticate.into()).await?;
+
+    match next(framed, "Ack").await? {
+        Packet::Ack(ack) if ack.success => Ok(()),
+        Packet::Ack(ack) => Err(rejected(ack)),
+        _ => Err(ClientError::Handshake("Ack")),
+    }
+}
+
+impl PartyPanelClient {
+    /// Dials a mod running in listen mode
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        options: ClientOptions,
+    ) -> Result<Self, ClientError> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+
+        Self::handshake(stream, options).await
+    }
+
+    /// Introduces the panel over an already open connection, e.g. one the
+    /// mod dialed in connect mode, and authenticates if the mod asks to
+    pub async fn handshake<S>(stream: S, options: ClientOptions) -> Result<Self, ClientError>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let codec = PartyPanelCodec::new()
+            .with_encoding(Encoding::Protobuf)
+            .with_framing(Framing::Envelope);
+        let mut framed = Framed::new(stream, codec);
+
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: options.name.clone(),
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+            supported_packets: SUPPORTED_PACKETS.iter().map(|p| *p as i32).collect(),
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        };
+        framed.send(hello.into()).await?;
+
+        let Packet::Welcome(welcome) = next(&mut framed, "Welcome").await? else {
+            return Err(ClientError::Handshake("Welcome"));
+        };
+        if !welcome.accepted {
+            return Err(ClientError::Refused(welcome.reason));
+        }
+        if !welcome.auth_challenge.is_empty() {
+            authenticate(
+                &mut framed,
+                options.credentials.as_ref(),
+                &welcome.auth_challenge,
+            )
+            .await?;
+        }
+
+        let supports = |capability: &str| welcome.capabilities.iter().any(|c| c == capability);
+        framed
+            .codec()
+            .set_compression(supports(COMPRESSION_CAPABILITY));
+        let heartbeats = supports(HEARTBEAT_CAPABILITY);
+        let deltas = supports(SONG_LIST_DELTAS_CAPABILITY);
+
+        let (events, _) = broadcast::channel(EVENT_QUEUE_SIZE);
+        let shared = Arc::new(Shared {
+            pending: Mutex::default(),
+            library: Mutex::default(),
+            songs: watch::Sender::new(SongsState::Loading),
+            now_playing: Mutex::default(),
+            round_trip: Mutex::default(),
+            events,
+        });
+
+        let (outbound, mut outbound_rx) = mpsc::unbounded_channel::<Packet>();
+        // the mod only sends panels taking deltas the song list when asked
+        if deltas {
+            let _ = outbound.send(SyncSongs::default().into());
+        }
+        let (mut sink, stream) = framed.split();
+
+        let writer = tokio::spawn(async move {
+            while let Some(packet) = outbound_rx.recv().await {
+                if let Err(e) = sink.send(packet).await {
+                    warn!("Failed to send to the mod: {e}");
+                    break;
+                }
+            }
+        });
+        // only sent when the mod supports them
+        let heartbeat = heartbeats
+            .then(|| Heartbeat::new(options.heartbeat_interval, Some(options.heartbeat_timeout)));
+        let reader = tokio::spawn(read_loop(
+            stream,
+            shared.clone(),
+            outbound.clone(),
+            heartbeat,
+        ));
+
+        Ok(Self {
+            welcome,
+            request_timeout: options.request_timeout,
+            outbound,
+            shared,
+            next_request_id: AtomicU32::new(1),
+            tasks: [writer, reader],
+        })
+    }
+
+    /// The mod's answer to the handshake, with its versions and capabilities
+    pub fn welcome(&self) -> &Welcome {
+        &self.welcome
+    }
+
+    /// The latest heartbeat round trip, if the mod supports heartbeats
+    pub fn round_trip(&self) -> Option<Duration> {
+        *self.shared.round_trip.lock().unwrap()
+    }
+
+    /// What the mod last said is playing
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.shared.now_playing.lock().unwrap().clone()
+    }
+
+    /// The mod's song library, waiting for the first complete list if it
+    /// hasn't arrived yet
+    pub async fn songs(&self) -> Result<Vec<PreviewBeatmapLevel>, ClientError> {
+        let mut songs = self.shared.songs.subscribe();
+        let state = *songs
+            .wait_for(|state| *state != SongsState::Loading)
+            .await
+            .map_err(|_| ClientError::Closed)?;
+
+        let library = self.shared.library.lock().unwrap();
+        if state == SongsState::Closed && !library.is_loaded() {
+            return Err(ClientError::Closed);
+        }
+
+        Ok(library.levels().to_vec())
+    }
+
+    /// Everything the mod pushes from now on
+    pub fn events(&self) -> impl Stream<Item = Event> {
+        futures::stream::unfold(self.shared.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Missed {missed} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// Sends a packet with a fresh request id and waits for its `Ack`
+    async fn request(&self, mut packet: Packet) -> Result<(), ClientError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        packet.set_request_id(request_id);
+
+        let (sender, receiver) = oneshot::channel();
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .insert(request_id, sender);
+
+        if self.outbound.send(packet).is_err() {
+            self.shared.pending.lock().unwrap().remove(&request_id);
+            return Err(ClientError::Closed);
+        }
+
+        let ack = match tokio::time::timeout(self.request_timeout, receiver).await {
+            Ok(ack) => ack.map_err(|_| ClientError::Closed)?,
+            Err(_) => {
+                self.shared.pending.lock().unwrap().remove(&request_id);
+                return Err(ClientError::Timeout("Ack"));
+            }
+        };
+
+        if ack.success {
+            Ok(())
+        } else {
+            Err(rejected(ack))
+        }
+    }
+
+    /// Starts a level. Sent as is if the mod takes the typed `PlaySong`,
+    /// otherwise fails before sending anything if the song doesn't convert to
+    /// a valid v1 one.
+    pub async fn play_song(&self, song: v2::PlaySong) -> Result<(), ClientError> {
+        let typed = self
+            .welcome
+            .supported_packets
+            .contains(&(PacketType::PlaySongV2 as i32));
+        if typed {
+            return self.request(song.into()).await;
+        }
+
+        let song = PlaySong::try_from(song)?;
+        self.request(song.into()).await
+    }
+
+    pub async fn return_to_menu(&self) -> Result<(), ClientError> {
+        let command = Command {
+            command_type: CommandType::ReturnToMenu as i32,
+            ..Default::default()
+        };
+        self.request(command.into()).await
+    }
+}
+
+impl Shared {
+    fn handle(
+        &self,
+        packet: Packet,
+        outbound: &mpsc::UnboundedSender<Packet>,
+        heartbeat: Option<&mut Heartbeat>,
+    ) {
+        match packet {
+            Packet::Ack(ack) => {
+                if let Some(sender) = self.pending.lock().unwrap().remove(&ack.request_id) {
+                    let _ = sender.send(ack);
+                }
+            }
+            Packet::Command(command) if heartbeat::is_heartbeat(&command) => {
+                let Some(heartbeat) = heartbeat else {
+                    return;
+                };
+                if let Some(echo) = heartbeat.handle(&command) {
+                    let _ = outbound.send(echo.into());
+                }
+                *self.round_trip.lock().unwrap() = heartbeat.round_trip();
+            }
+            Packet::NowPlaying(now_playing) => {
+                *self.now_playing.lock().unwrap() = Some(now_playing.clone());
+                let _ = self.events.send(Event::NowPlaying(now_playing));
+            }
+            Packet::NowPlayingUpdate(update) => {
+                let _ = self.events.send(Event::NowPlayingUpdate(update));
+            }
+            Packet::ErrorEvent(error) => {
+                let _ = self.events.send(Event::Error(error));
+            }
+            packet => {
+                let mut library = self.library.lock().unwrap();
+                match library.apply(&packet) {
+                    Applied::Changed if library.is_loaded() => {
+                        self.songs.send_replace(SongsState::Loaded);
+                        let _ = self.events.send(Event::SongsChanged {
+                            revision: library.revision(),
+                        });
+                    }
+                    Applied::OutOfSync => {
+                        let sync = SyncSongs {
+                            since_revision: library.revision(),
+                            request_id: 0,
+                            library_epoch: library.epoch(),
+                        };
+                        let _ = outbound.send(sync.into());
+                    }
+                    Applied::Changed | Applied::Unchanged => {}
+                }
+            }
+        }
+    }
+}
+
+async fn read_loop<S>(
+    mut stream: S,
+    shared: Arc<Shared>,
+    outbound: mpsc::UnboundedSender<Packet>,
+    mut heartbeat: Option<Heartbeat>,
+) where
+    S: Stream<Item = Result<Packet, ProtocolError>> + Unpin,
+{
+    let interval = heartbeat
+        .as_ref()
+        .map_or(Duration::from_secs(60), Heartbeat::interval);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let deadline = heartbeat.as_ref().and_then(Heartbeat::deadline);
+        // not polled without a deadline
+        let quiet = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now));
+
+        let packet = tokio::select! {
+            packet = stream.next() => packet,
+            _ = ticker.tick(), if heartbeat.is_some() => {
+                if let Some(heartbeat) = heartbeat.as_mut() {
+                    let _ = outbound.send(heartbeat.ping().into());
+                }
+                continue;
+            }
+            _ = quiet, if deadline.is_some() => {
+                warn!("The mod stopped responding");
+                break;
+            }
+        };
+
+        let packet = match packet {
+            Some(Ok(packet)) => packet,
+            Some(Err(e)) => {
+                warn!("Connection to the mod failed: {e}");
+                break;
+            }
+            None => break,
+        };
+        if let Some(heartbeat) = heartbeat.as_mut() {
+            heartbeat.received();
+        }
+
+        shared.handle(packet, &outbound, heartbeat.as_mut());
+    }
+
+    // dropping the senders fails every request still waiting
+    shared.pending.lock().unwrap().clear();
+    shared.songs.send_replace(SongsState::Closed);
+    let _ = shared.events.send(Event::Disconnected);
+}
@@ -0,0 +1,77 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use party_panel_protocol::{
+    discovery::{decode_datagram, encode_datagram, MAX_DATAGRAM_SIZE},
+    error::ProtocolError,
+    proto::{
+        packets::{DiscoveryAnnouncement, DiscoveryQuery},
+        Packet, PROTOCOL_VERSION,
+    },
+};
+
+/// Sends a `DiscoveryQuery` to `target` and collects every headset that
+/// answers within `wait`.
+///
+/// `target` is usually the broadcast address, e.g.
+/// `255.255.255.255:`[`DEFAULT_DISCOVERY_PORT`](party_panel_protocol::discovery::DEFAULT_DISCOVERY_PORT),
+/// or the [`DISCOVERY_MULTICAST_ADDR`](party_panel_protocol::discovery::DISCOVERY_MULTICAST_ADDR),
+/// but any single headset (or loopback) works too.
+pub async fn discover(
+    target: SocketAddr,
+    wait: Duration,
+) -> Result<Vec<(SocketAddr, DiscoveryAnnouncement)>, ProtocolError> {
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+
+    let query = DiscoveryQuery {
+        protocol_version: PROTOCOL_VERSION,
+    };
+    socket.send_to(&encode_datagram(query)?, target).await?;
+
+    let mut found = Vec::new();
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+    let deadline = tokio::time::Instant::now() + wait;
+
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, peer) = received?;
+        if let Some(Packet::DiscoveryAnnouncement(announcement)) = decode_datagram(&buf[..len]) {
+            found.push((peer, announcement));
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use party_panel_protocol::discovery::respond;
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_responder_on_loopback() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let target = socket.local_addr().unwrap();
+        let announcement = DiscoveryAnnouncement {
+            name: "Loopback".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            listening: true,
+            listen_port: 8080,
+            ..Default::default()
+        };
+
+        let expected = announcement.clone();
+        let responder = tokio::spawn(async move {
+            respond(&socket, || announcement.clone()).await.unwrap();
+        });
+
+        let found = discover(target, Duration::from_millis(500)).await.unwrap();
+        responder.abort();
+
+        assert_eq!(found, [(target, expected)]);
+    }
+}
@@ -0,0 +1,39 @@
+use party_panel_protocol::{
+    error::{ProtocolError, ValidationError},
+    proto::packets::ack::ErrorCode,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+
+    #[error("Connection closed")]
+    Closed,
+
+    #[error("Refused by the mod: {0}")]
+    Refused(String),
+
+    /// The mod sent something else where the handshake needed a specific packet
+    #[error("Expected {0} during the handshake")]
+    Handshake(&'static str),
+
+    #[error("The mod requires authentication, but no credentials were given")]
+    AuthRequired,
+
+    /// The mod answered a request with a failed `Ack`
+    #[error("{code:?}: {message}")]
+    Rejected { code: ErrorCode, message: String },
+
+    #[error("Timed out waiting for {0}")]
+    Timeout(&'static str),
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Protocol(value.into())
+    }
+}
@@ -0,0 +1,13 @@
+mod client;
+mod discovery;
+mod error;
+mod library;
+
+pub use client::{
+    ClientOptions, Credentials, Event, PartyPanelClient, CAPABILITIES, HANDSHAKE_TIMEOUT,
+    SUPPORTED_PACKETS,
+};
+pub use discovery::discover;
+pub use error::ClientError;
+/// The messages and framing shared with the mod
+pub use party_panel_protocol as protocol;
@@ -0,0 +1,331 @@
+use party_panel_protocol::proto::{items::PreviewBeatmapLevel, Packet};
+
+/// What a song list packet did to the [`Library`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Applied {
+    Unchanged,
+    Changed,
+    /// A delta that doesn't follow our revision, the mod has to be asked for
+    /// the changes since it with `SyncSongs`
+    OutOfSync,
+}
+
+struct PendingList {
+    list_id: u64,
+    levels: Vec<PreviewBeatmapLevel>,
+}
+
+/// The removals and changes of a revision, held back until its `SongsAdded`
+/// so the levels never show half of a revision
+#[derive(Default)]
+struct PendingDelta {
+    previous_revision: u64,
+    removed: Vec<String>,
+    changed: Vec<PreviewBeatmapLevel>,
+}
+
+/// The mod's song library as far as the panel knows, built from whole lists,
+/// pages and deltas
+#[derive(Default)]
+pub struct Library {
+    levels: Vec<PreviewBeatmapLevel>,
+    /// Which run of the mod `revision` is from
+    epoch: u64,
+    revision: u64,
+    loaded: bool,
+    pending: Option<PendingList>,
+    delta: Option<PendingDelta>,
+}
+
+impl Library {
+    pub fn levels(&self) -> &[PreviewBeatmapLevel] {
+        &self.levels
+    }
+
+    /// 0 until a list with a revision has arrived
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// 0 until a list with a revision has arrived
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Whether a complete list has arrived yet
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// The delta being collected for the revision after `previous_revision`
+    fn delta(&mut self, previous_revision: u64) -> &mut PendingDelta {
+        self.delta
+            .take_if(|delta| delta.previous_revision != previous_revision);
+        self.delta.get_or_insert_with(|| PendingDelta {
+            previous_revision,
+            ..Default::default()
+        })
+    }
+
+    /// Only whole lists and `SongsAdded`, which completes a delta and advances
+    /// the revision, change the levels
+    pub fn apply(&mut self, packet: &Packet) -> Applied {
+        match packet {
+            Packet::SongList(list) => {
+                self.levels = list.levels.clone();
+                self.delta = None;
+                self.loaded = true;
+                Applied::Changed
+            }
+            Packet::SongListBegin(begin) => {
+                // a new list replaces one still in progress
+                self.pending = Some(PendingList {
+                    list_id: begin.list_id,
+                    levels: Vec::with_capacity(begin.total_levels as usize),
+                });
+                Applied::Unchanged
+            }
+            Packet::SongListPage(page) => {
+                if let Some(pending) = self
+                    .pending
+                    .as_mut()
+                    .filter(|pending| pending.list_id == page.list_id)
+                {
+                    pending.levels.extend(page.levels.iter().cloned());
+                }
+                Applied::Unchanged
+            }
+            Packet::SongListEnd(end) => match self.pending.take() {
+                Some(pending) if pending.list_id == end.list_id => {
+                    self.levels = pending.levels;
+                    self.delta = None;
+                    self.epoch = end.library_epoch;
+                    self.revision = end.revision;
+                    self.loaded = true;
+                    Applied::Changed
+                }
+                pending => {
+                    self.pending = pending;
+                    Applied::Unchanged
+                }
+            },
+            // removals and changes come before the SongsAdded of the same
+            // revision, which is the one that asks to resync if we missed some
+            Packet::SongsRemoved(removed) if removed.previous_revision == self.revision => {
+                let delta = self.delta(removed.previous_revision);
+                delta.removed.extend(removed.level_ids.iter().cloned());
+                Applied::Unchanged
+            }
+            Packet::SongsChanged(changed) if changed.previous_revision == self.revision => {
+                let delta = self.delta(changed.previous_revision);
+                delta.changed.extend(changed.levels.iter().cloned());
+                Applied::Unchanged
+            }
+            Packet::SongsAdded(added) if added.previous_revision == self.revision => {
+                let delta = self
+                    .delta
+                    .take()
+                    .filter(|delta| delta.previous_revision == added.previous_revision)
+                    .unwrap_or_default();
+
+                self.levels
+                    .retain(|level| !delta.removed.contains(&level.level_id));
+                for level in delta.changed {
+                    if let Some(old) = self
+                        .levels
+                        .iter_mut()
+                        .find(|old| old.level_id == level.level_id)
+                    {
+                        *old = level;
+                    }
+                }
+                self.levels.extend(added.levels.iter().cloned());
+                self.revision = added.revision;
+                Applied::Changed
+            }
+            Packet::SongsAdded(_) => {
+                self.delta = None;
+                Applied::OutOfSync
+            }
+            _ => Applied::Unchanged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use party_panel_protocol::proto::packets::{
+        SongList, SongListBegin, SongListEnd, SongListPage, SongsAdded, SongsChanged, SongsRemoved,
+    };
+
+    use super::*;
+
+    fn level(id: &str, name: &str) -> PreviewBeatmapLevel {
+        PreviewBeatmapLevel {
+            level_id: id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(library: &Library) -> Vec<&str> {
+        library
+            .levels()
+            .iter()
+            .map(|level| level.level_id.as_str())
+            .collect()
+    }
+
+    /// `levels` in pages of `page_size`, at `revision`
+    fn paged(levels: &[PreviewBeatmapLevel], page_size: usize, revision: u64) -> Vec<Packet> {
+        let mut packets = vec![SongListBegin {
+            list_id: revision,
+            total_levels: levels.len() as u32,
+            page_size: page_size as u32,
+        }
+        .into()];
+        for (page, chunk) in levels.chunks(page_size).enumerate() {
+            packets.push(
+                SongListPage {
+                    list_id: revision,
+                    page: page as u32,
+                    levels: chunk.to_vec(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        packets.push(
+            SongListEnd {
+                list_id: revision,
+                pages: levels.chunks(page_size).len() as u32,
+                levels: levels.len() as u32,
+                revision,
+                library_epoch: 7,
+                ..Default::default()
+            }
+            .into(),
+        );
+        packets
+    }
+
+    fn loaded(levels: &[PreviewBeatmapLevel], revision: u64) -> Library {
+        let mut library = Library::default();
+        for packet in paged(levels, 2, revision) {
+            library.apply(&packet);
+        }
+        library
+    }
+
+    #[test]
+    fn pages_load_on_the_end() {
+        let mut library = Library::default();
+        let levels = [level("a", "A"), level("b", "B"), level("c", "C")];
+
+        let packets = paged(&levels, 2, 3);
+        let (end, pages) = packets.split_last().unwrap();
+        for packet in pages {
+            assert_eq!(library.apply(packet), Applied::Unchanged);
+        }
+        assert!(!library.is_loaded());
+
+        assert_eq!(library.apply(end), Applied::Changed);
+        assert!(library.is_loaded());
+        assert_eq!(ids(&library), ["a", "b", "c"]);
+        assert_eq!((library.epoch(), library.revision()), (7, 3));
+    }
+
+    #[test]
+    fn pages_of_another_list_are_ignored() {
+        let mut library = Library::default();
+        let old = paged(&[level("a", "A")], 2, 1);
+        let new = paged(&[level("b", "B")], 2, 2);
+
+        library.apply(&old[0]);
+        library.apply(&new[0]);
+        library.apply(&old[1]);
+        library.apply(&new[1]);
+        assert_eq!(library.apply(&old[2]), Applied::Unchanged);
+        assert_eq!(library.apply(&new[2]), Applied::Changed);
+        assert_eq!(ids(&library), ["b"]);
+    }
+
+    #[test]
+    fn whole_lists_replace_the_library() {
+        let mut library = loaded(&[level("a", "A")], 1);
+
+        let list = SongList {
+            levels: vec![level("b", "B")],
+        };
+        assert_eq!(library.apply(&list.into()), Applied::Changed);
+        assert_eq!(ids(&library), ["b"]);
+    }
+
+    #[test]
+    fn deltas_apply_in_order_on_songs_added() {
+        let mut library = loaded(&[level("a", "A"), level("b", "B"), level("c", "C")], 1);
+
+        let removed = SongsRemoved {
+            revision: 2,
+            previous_revision: 1,
+            level_ids: vec!["c".to_string()],
+        };
+        let changed = SongsChanged {
+            revision: 2,
+            previous_revision: 1,
+            levels: vec![level("b", "Renamed")],
+        };
+        assert_eq!(library.apply(&removed.into()), Applied::Unchanged);
+        assert_eq!(library.apply(&changed.into()), Applied::Unchanged);
+        // nothing shows until the revision is complete
+        assert_eq!(ids(&library), ["a", "b", "c"]);
+        assert_eq!(library.revision(), 1);
+
+        let added = SongsAdded {
+            revision: 2,
+            previous_revision: 1,
+            levels: vec![level("d", "D")],
+        };
+        assert_eq!(library.apply(&added.into()), Applied::Changed);
+        assert_eq!(ids(&library), ["a", "b", "d"]);
+        assert_eq!(library.levels()[1].name, "Renamed");
+        assert_eq!(library.revision(), 2);
+
+        let added = SongsAdded {
+            revision: 3,
+            previous_revision: 2,
+            levels: vec![level("e", "E")],
+        };
+        assert_eq!(library.apply(&added.into()), Applied::Changed);
+        assert_eq!(ids(&library), ["a", "b", "d", "e"]);
+        assert_eq!(library.revision(), 3);
+    }
+
+    #[test]
+    fn stale_deltas_ask_to_resync() {
+        let mut library = loaded(&[level("a", "A")], 1);
+
+        // revision 2 never arrived
+        let removed = SongsRemoved {
+            revision: 3,
+            previous_revision: 2,
+            level_ids: vec!["a".to_string()],
+        };
+        let added = SongsAdded {
+            revision: 3,
+            previous_revision: 2,
+            levels: vec![level("b", "B")],
+        };
+        assert_eq!(library.apply(&removed.into()), Applied::Unchanged);
+        assert_eq!(library.apply(&added.into()), Applied::OutOfSync);
+        assert_eq!(ids(&library), ["a"]);
+        assert_eq!(library.revision(), 1);
+
+        // the resync brings the whole list at the latest revision
+        for packet in paged(&[level("b", "B")], 2, 3) {
+            library.apply(&packet);
+        }
+        assert_eq!(ids(&library), ["b"]);
+        assert_eq!(library.revision(), 3);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{bail, Context};
+use party_panel_client::{
+    protocol::proto::{
+        items::GameplayModifiers,
+        v2::{Characteristic, Difficulty},
+    },
+    Credentials,
+};
+
+use crate::modifiers;
+
+pub const USAGE: &str = "\
+Usage: ppctl (--connect <ADDR> | --listen <ADDR>) [OPTIONS] <COMMAND>
+
+Commands:
+  songs [QUERY]            List the mod's songs, or those whose name, artist,
+                           mapper or level id contain QUERY
+  play <LEVEL_ID>          Start a level, needs --difficulty
+  menu                     Return to the main menu
+  now-playing              Print what is playing and its progress until the
+                           connection closes
+
+Options:
+  -c, --connect <ADDR>     Dial a mod in listen mode
+  -l, --listen <ADDR>      Wait for a mod in connect mode to dial in over TCP
+      --token <TOKEN>      The mod's auth_token
+      --device <ID>        A paired device's id, with --secret
+      --secret <SECRET>    A paired device's secret, with --device
+      --json               Print one JSON object per line
+  -d, --difficulty <NAME>  Easy, Normal, Hard, Expert or ExpertPlus
+      --characteristic <NAME>
+                           Standard, OneSaber, NoArrows, 360Degree, 90Degree,
+                           Lightshow, Lawless or a custom one [default: Standard]
+  -m, --modifiers <LIST>   Comma separated, e.g. no-fail,faster-song
+  -h, --help               Print this help
+";
+
+/// Which side opens the connection
+pub enum Target {
+    Connect(String),
+    Listen(String),
+}
+
+pub enum Command {
+    Songs {
+        query: Option<String>,
+    },
+    Play {
+        level_id: String,
+        difficulty: Difficulty,
+        characteristic: Characteristic,
+        modifiers: GameplayModifiers,
+    },
+    Menu,
+    NowPlaying,
+}
+
+pub struct Args {
+    pub target: Target,
+    pub credentials: Option<Credentials>,
+    pub json: bool,
+    pub command: Command,
+}
+
+/// `None` if help was asked for
+pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut args = args.into_iter();
+    let mut target = None;
+    let mut token = None;
+    let mut device = None;
+    let mut secret = None;
+    let mut json = false;
+    let mut difficulty = None;
+    let mut characteristic = None;
+    let mut modifiers = GameplayModifiers::default();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-c" | "--connect" => target = Some(Target::Connect(value()?)),
+            "-l" | "--listen" => target = Some(Target::Listen(value()?)),
+            "--token" => token = Some(value()?),
+            "--device" => device = Some(value()?),
+            "--secret" => secret = Some(value()?),
+            "--json" => json = true,
+            "-d" | "--difficulty" => difficulty = Some(Difficulty::from_name(&value()?)?),
+            "--characteristic" => {
+                characteristic = Some(Characteristic::from_serialized_name(&value()?))
+            }
+            "-m" | "--modifiers" => modifiers::apply(&mut modifiers, &value()?)?,
+            flag if flag.starts_with('-') => bail!("Unknown option {flag}"),
+            _ => positional.push(arg),
+        }
+    }
+
+    let target = target.context("Pass --connect or --listen")?;
+    let credentials = match (token, device, secret) {
+        (Some(token), None, None) => Some(Credentials::Token(token)),
+        (None, Some(id), Some(secret)) => Some(Credentials::Device { id, secret }),
+        (None, None, None) => None,
+        _ => bail!("Pass either --token or both --device and --secret"),
+    };
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("songs") => Command::Songs {
+            query: positional.next(),
+        },
+        Some("play") => Command::Play {
+            level_id: positional.next().context("play needs a level id")?,
+            difficulty: difficulty.context("play needs --difficulty")?,
+            characteristic: characteristic
+                .unwrap_or_else(|| Characteristic::from_serialized_name("Standard")),
+            modifiers,
+        },
+        Some("menu") => Command::Menu,
+        Some("now-playing") => Command::NowPlaying,
+        Some(command) => bail!("Unknown command {command}"),
+        None => bail!("Missing a command"),
+    };
+    if let Some(extra) = positional.next() {
+        bail!("Unexpected argument {extra}");
+    }
+
+    Ok(Some(Args {
+        target,
+        credentials,
+        json,
+        command,
+    }))
+}
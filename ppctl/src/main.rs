@@ -0,0 +1,211 @@
+mod args;
+mod modifiers;
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context};
+use args::{Args, Command, Target};
+use futures::StreamExt;
+use party_panel_client::{
+    protocol::{
+        proto::{
+            items::PreviewBeatmapLevel,
+            packets::{ErrorEvent, NowPlaying, NowPlayingUpdate},
+            v2,
+        },
+        schema::format_duration,
+    },
+    ClientOptions, Event, PartyPanelClient,
+};
+use serde_json::json;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", args::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("ppctl: {e:#}\n\n{}", args::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ppctl: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn connect(args: &Args) -> anyhow::Result<PartyPanelClient> {
+    let options = ClientOptions {
+        name: "ppctl".to_string(),
+        credentials: args.credentials.clone(),
+        ..Default::default()
+    };
+
+    let client = match &args.target {
+        Target::Connect(addr) => PartyPanelClient::connect(addr.as_str(), options)
+            .await
+            .with_context(|| format!("Failed to connect to {addr}"))?,
+        Target::Listen(addr) => {
+            let listener = TcpListener::bind(addr.as_str())
+                .await
+                .with_context(|| format!("Failed to listen on {addr}"))?;
+            eprintln!("Waiting for the mod on {}", listener.local_addr()?);
+
+            let (stream, peer) = listener.accept().await?;
+            stream.set_nodelay(true)?;
+            PartyPanelClient::handshake(stream, options)
+                .await
+                .with_context(|| format!("Handshake with {peer} failed"))?
+        }
+    };
+
+    Ok(client)
+}
+
+async fn run(args: Args) -> anyhow::Result<()> {
+    let client = connect(&args).await?;
+
+    match args.command {
+        Command::Songs { query } => {
+            let songs = client.songs().await?;
+            for level in songs
+                .into_iter()
+                .filter(|level| query.as_deref().is_none_or(|query| matches(level, query)))
+            {
+                print_level(level, args.json)?;
+            }
+        }
+        Command::Play {
+            level_id,
+            difficulty,
+            characteristic,
+            modifiers,
+        } => {
+            let song = v2::PlaySong {
+                level_id,
+                difficulty: difficulty as i32,
+                characteristic: Some(characteristic),
+                gameplay_modifiers: Some(modifiers),
+                request_id: 0,
+            };
+            client.play_song(song).await?;
+        }
+        Command::Menu => client.return_to_menu().await?,
+        Command::NowPlaying => tail(&client, args.json).await?,
+    }
+
+    Ok(())
+}
+
+/// Case insensitive search over what a panel shows for a level
+fn matches(level: &PreviewBeatmapLevel, query: &str) -> bool {
+    let query = query.to_lowercase();
+    [
+        &level.level_id,
+        &level.name,
+        &level.sub_name,
+        &level.author,
+        &level.mapper,
+    ]
+    .iter()
+    .any(|field| field.to_lowercase().contains(&query))
+}
+
+fn print_level(mut level: PreviewBeatmapLevel, json: bool) -> anyhow::Result<()> {
+    if json {
+        // covers are too big for a terminal, cover_path still points at them
+        level.cover.clear();
+        println!("{}", serde_json::to_string(&level)?);
+        return Ok(());
+    }
+
+    let characteristics: Vec<_> = level
+        .chars
+        .iter()
+        .map(|c| format!("{} ({})", c.name, c.diffs.join(", ")))
+        .collect();
+    println!(
+        "{}\t{} - {}\t{}\t{}\t{}",
+        level.level_id,
+        level.author,
+        level.name,
+        level.mapper,
+        level.duration,
+        characteristics.join(", ")
+    );
+    Ok(())
+}
+
+/// Prints now playing events until the mod goes away
+async fn tail(client: &PartyPanelClient, json: bool) -> anyhow::Result<()> {
+    let mut events = std::pin::pin!(client.events());
+
+    // the mod sends what's playing right after the handshake, which may have
+    // arrived before we subscribed
+    let mut last = client.now_playing();
+    if let Some(now_playing) = &last {
+        print_now_playing(now_playing, json);
+    }
+
+    while let Some(event) = events.next().await {
+        match event {
+            Event::NowPlaying(now_playing) if last.as_ref() == Some(&now_playing) => {}
+            Event::NowPlaying(now_playing) => {
+                print_now_playing(&now_playing, json);
+                last = Some(now_playing);
+            }
+            Event::NowPlayingUpdate(update) => print_update(&update, json),
+            Event::Error(error) => print_error(&error, json),
+            Event::SongsChanged { .. } => {}
+            Event::Disconnected => break,
+        }
+    }
+
+    bail!("The mod disconnected")
+}
+
+fn print_now_playing(now_playing: &NowPlaying, json: bool) {
+    if json {
+        println!("{}", json!({ "now_playing": now_playing }));
+    } else if now_playing.is_finished {
+        println!("Finished {}", now_playing.level_id);
+    } else {
+        println!("Playing {}", now_playing.level_id);
+    }
+}
+
+fn print_update(update: &NowPlayingUpdate, json: bool) {
+    if json {
+        println!("{}", json!({ "now_playing_update": update }));
+    } else {
+        println!(
+            "{} / {}\tscore {}\taccuracy {:.2}%",
+            format_duration(update.elapsed as f32),
+            format_duration(update.total_time as f32),
+            update.score,
+            update.accuracy * 100.0
+        );
+    }
+}
+
+fn print_error(error: &ErrorEvent, json: bool) {
+    if json {
+        println!("{}", json!({ "error": error }));
+    } else {
+        eprintln!(
+            "{:?} from {:?}: {}",
+            error.severity(),
+            error.source(),
+            error.message
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::bail;
+use party_panel_client::protocol::proto::items::{
+    gameplay_modifiers::{EnabledObstacleType, EnergyType, SongSpeed},
+    GameplayModifiers,
+};
+
+type Enable = fn(&mut GameplayModifiers);
+
+/// Every modifier `--modifiers` accepts, named after the game's modifier panel
+pub const MODIFIERS: &[(&str, Enable)] = &[
+    ("no-fail", |m| m.no_fail_on_0_energy = true),
+    ("one-life", |m| m.insta_fail = true),
+    ("four-lives", |m| m.set_energy_type(EnergyType::Battery)),
+    ("no-bombs", |m| m.no_bombs = true),
+    ("no-walls", |m| {
+        m.set_enabled_obstacle_type(EnabledObstacleType::NoObstacles)
+    }),
+    ("no-arrows", |m| m.no_arrows = true),
+    ("ghost-notes", |m| m.ghost_notes = true),
+    ("disappearing-arrows", |m| m.disappearing_arrows = true),
+    ("small-notes", |m| m.small_cubes = true),
+    ("pro-mode", |m| m.pro_mode = true),
+    ("strict-angles", |m| m.strict_angles = true),
+    ("zen-mode", |m| m.zen_mode = true),
+    ("slower-song", |m| m.set_song_speed(SongSpeed::Slower)),
+    ("faster-song", |m| m.set_song_speed(SongSpeed::Faster)),
+    ("super-fast-song", |m| {
+        m.set_song_speed(SongSpeed::SuperFast)
+    }),
+];
+
+/// Turns on every modifier in a comma separated list
+pub fn apply(modifiers: &mut GameplayModifiers, list: &str) -> anyhow::Result<()> {
+    for name in list
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let Some((_, enable)) = MODIFIERS.iter().find(|(known, _)| *known == name) else {
+            let known: Vec<_> = MODIFIERS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "Unknown modifier {name}, expected one of {}",
+                known.join(", ")
+            );
+        };
+        enable(modifiers);
+    }
+
+    Ok(())
+}
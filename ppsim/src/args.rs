@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use party_panel_protocol::discovery::DEFAULT_DISCOVERY_PORT;
+
+/// What `--time-scale` accepts
+const TIME_SCALES: std::ops::RangeInclusive<f32> = 0.01..=1000.0;
+
+pub const USAGE: &str = "\
+Usage: ppsim [OPTIONS]
+
+Pretends to be a headset running the mod, for developing panels without one.
+
+Options:
+  -l, --listen <ADDR>       Wait for panels on ADDR [default: 0.0.0.0:8080]
+  -c, --connect <ADDR>      Dial a panel at ADDR instead, like the mod's connect
+                            mode, and redial whenever it goes away
+      --library <PATH>      A JSON file of levels, or a directory of such files
+                            [default: a few built-in levels]
+      --token <TOKEN>       Make panels authenticate with TOKEN
+      --websocket           Carry packets in WebSocket messages, like the mod's
+                            websocket transport for browser panels
+      --name <NAME>         What discovery calls the headset [default: ppsim]
+      --time-scale <N>      Play levels N times faster than real time, from 0.01
+                            to 1000 [default: 1]
+      --discovery-port <PORT>
+                            Answer discovery queries on PORT, 0 to not answer
+                            them [default: 48080]
+  -h, --help                Print this help
+";
+
+/// Which side opens the connection
+pub enum Target {
+    Listen(String),
+    Connect(String),
+}
+
+pub struct Args {
+    pub target: Target,
+    pub library: Option<PathBuf>,
+    pub token: Option<String>,
+    pub websocket: bool,
+    pub name: String,
+    pub time_scale: f32,
+    pub discovery_port: u16,
+}
+
+/// `None` if help was asked for
+pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut args = args.into_iter();
+    let mut parsed = Args {
+        target: Target::Listen("0.0.0.0:8080".to_string()),
+        library: None,
+        token: None,
+        websocket: false,
+        name: "ppsim".to_string(),
+        time_scale: 1.0,
+        discovery_port: DEFAULT_DISCOVERY_PORT,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-l" | "--listen" => parsed.target = Target::Listen(value()?),
+            "-c" | "--connect" => parsed.target = Target::Connect(value()?),
+            "--library" => parsed.library = Some(value()?.into()),
+            "--token" => parsed.token = Some(value()?),
+            "--websocket" => parsed.websocket = true,
+            "--name" => parsed.name = value()?,
+            "--time-scale" => {
+                parsed.time_scale = value()?.parse().context("Invalid --time-scale")?;
+                // also keeps the update interval something tokio can sleep for
+                if !TIME_SCALES.contains(&parsed.time_scale) {
+                    bail!(
+                        "--time-scale has to be between {} and {}",
+                        TIME_SCALES.start(),
+                        TIME_SCALES.end()
+                    );
+                }
+            }
+            "--discovery-port" => {
+                parsed.discovery_port = value()?.parse().context("Invalid --discovery-port")?
+            }
+            _ => bail!("Unexpected argument {arg}"),
+        }
+    }
+
+    Ok(Some(parsed))
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use party_panel_protocol::{
+    error::CommandError,
+    pairing::{self, PinSlot, PIN_TTL},
+    proto::packets::{device_list, Paired},
+};
+use tokio::sync::broadcast;
+
+/// Revocations kept for a slow session before it misses some
+const REVOKED_QUEUE_SIZE: usize = 16;
+
+pub static DEVICES: LazyLock<Devices> = LazyLock::new(Devices::new);
+
+struct Device {
+    id: String,
+    name: String,
+    secret: String,
+    /// unix seconds
+    paired_at: u64,
+}
+
+/// Paired panels, like the mod's but only kept until ppsim exits
+pub struct Devices {
+    devices: Mutex<Vec<Device>>,
+    pin: PinSlot,
+    /// Ids of revoked devices, so their sessions can end
+    revoked: broadcast::Sender<String>,
+}
+
+impl Devices {
+    fn new() -> Self {
+        let (revoked, _) = broadcast::channel(REVOKED_QUEUE_SIZE);
+
+        Self {
+            devices: Mutex::default(),
+            pin: PinSlot::default(),
+            revoked,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.lock().unwrap().is_empty()
+    }
+
+    pub fn secret(&self, device_id: &str) -> Option<String> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|device| device.id == device_id)
+            .map(|device| device.secret.clone())
+    }
+
+    pub fn list(&self) -> Vec<device_list::Device> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|device| device_list::Device {
+                device_id: device.id.clone(),
+                name: device.name.clone(),
+                paired_at: device.paired_at,
+            })
+            .collect()
+    }
+
+    /// Makes a new PIN and shows it where the game would
+    pub fn start_pairing(&self) -> String {
+        let pin = self.pin.start();
+        println!("Pairing PIN {pin}, valid for {}s", PIN_TTL.as_secs());
+
+        pin
+    }
+
+    /// Uses up the PIN and issues a credential for a new device
+    pub fn pair(&self, request_id: u32, pin: &str, name: String) -> Result<Paired, CommandError> {
+        self.pin.redeem(pin)?;
+
+        let paired_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let device = Device {
+            id: pairing::device_id(),
+            name,
+            secret: pairing::secret(),
+            paired_at,
+        };
+        println!("Paired device {} ({})", device.name, device.id);
+
+        let paired = Paired {
+            request_id,
+            device_id: device.id.clone(),
+            secret: device.secret.clone(),
+        };
+        self.devices.lock().unwrap().push(device);
+
+        Ok(paired)
+    }
+
+    /// Forgets a device and ends every session authenticated as it
+    pub fn revoke(&self, device_id: &str) -> Result<(), CommandError> {
+        let device = {
+            let mut devices = self.devices.lock().unwrap();
+            let Some(index) = devices.iter().position(|device| device.id == device_id) else {
+                return Err(CommandError::DeviceNotFound(device_id.to_string()));
+            };
+
+            devices.remove(index)
+        };
+        println!("Revoked device {} ({})", device.name, device.id);
+
+        // no sessions is fine
+        let _ = self.revoked.send(device.id);
+
+        Ok(())
+    }
+
+    /// Ids of devices revoked from now on
+    pub fn subscribe_revoked(&self) -> broadcast::Receiver<String> {
+        self.revoked.subscribe()
+    }
+}
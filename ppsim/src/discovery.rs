@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use party_panel_protocol::{
+    discovery::{bind, respond},
+    proto::{packets::DiscoveryAnnouncement, PROTOCOL_VERSION},
+};
+
+use crate::Config;
+
+fn announcement(config: &Config) -> DiscoveryAnnouncement {
+    DiscoveryAnnouncement {
+        name: config.name.clone(),
+        mod_version: crate::MOD_VERSION.to_string(),
+        game_version: crate::GAME_VERSION.to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        listening: config.listen_port.is_some(),
+        listen_port: config.listen_port.unwrap_or_default() as u32,
+        auth_required: config.auth_token.is_some(),
+        tls_fingerprint: String::new(),
+    }
+}
+
+/// Answers `DiscoveryQuery`s on `port` the same way the mod does
+pub async fn run(port: u16, config: Arc<Config>) -> anyhow::Result<()> {
+    let socket = bind(port)
+        .await
+        .with_context(|| format!("Failed to bind discovery port {port}"))?;
+
+    respond(&socket, || announcement(&config)).await?;
+
+    Ok(())
+}
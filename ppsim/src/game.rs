@@ -0,0 +1,173 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use party_panel_protocol::{
+    error::CommandError,
+    proto::{
+        items::{gameplay_modifiers::SongSpeed, PreviewBeatmapLevel},
+        packets::{NowPlaying, NowPlayingUpdate, PlaySong},
+        v2::Difficulty,
+        Packet,
+    },
+    schema::parse_duration,
+};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+/// How often the mod sends `NowPlayingUpdate` during a level
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Packets kept for a slow panel before it misses some
+const EVENT_QUEUE_SIZE: usize = 256;
+
+/// What the simulated player scores per second of song, and how accurately
+const SCORE_PER_SECOND: f32 = 1000.0;
+const ACCURACY: f64 = 0.92;
+
+struct Playing {
+    now_playing: NowPlaying,
+    task: JoinHandle<()>,
+}
+
+/// The simulated game, shared by every connected panel.
+///
+/// Levels "play" by counting their duration up in `NowPlayingUpdate`s, which
+/// are broadcast to every panel like the mod's.
+pub struct Game {
+    levels: Vec<PreviewBeatmapLevel>,
+    /// Song seconds that pass per real second
+    time_scale: f32,
+    playing: Mutex<Option<Playing>>,
+    events: broadcast::Sender<Packet>,
+}
+
+/// How much faster than normal the game plays a level with `song_speed`
+fn speed_multiplier(song_speed: SongSpeed) -> f32 {
+    match song_speed {
+        SongSpeed::Normal => 1.0,
+        SongSpeed::Faster => 1.2,
+        SongSpeed::Slower => 0.85,
+        SongSpeed::SuperFast => 1.5,
+    }
+}
+
+impl Game {
+    pub fn new(levels: Vec<PreviewBeatmapLevel>, time_scale: f32) -> Arc<Self> {
+        let (events, _) = broadcast::channel(EVENT_QUEUE_SIZE);
+
+        Arc::new(Self {
+            levels,
+            time_scale,
+            playing: Mutex::default(),
+            events,
+        })
+    }
+
+    pub fn levels(&self) -> &[PreviewBeatmapLevel] {
+        &self.levels
+    }
+
+    /// Packets for every panel from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Packet> {
+        self.events.subscribe()
+    }
+
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.playing
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|playing| playing.now_playing.clone())
+    }
+
+    /// Starts a level, refusing the same packets the mod would
+    pub fn play(self: &Arc<Self>, song: &PlaySong) -> Result<(), CommandError> {
+        let level = self
+            .levels
+            .iter()
+            .find(|level| level.level_id == song.level_id)
+            .ok_or_else(|| CommandError::LevelNotFound(song.level_id.clone()))?;
+
+        let characteristic_name = &song
+            .characteristic
+            .as_ref()
+            .ok_or_else(|| CommandError::InvalidPacket("Missing characteristic".into()))?
+            .name;
+        if !level.chars.iter().any(|c| &c.name == characteristic_name) {
+            return Err(CommandError::CharacteristicNotFound(
+                characteristic_name.clone(),
+            ));
+        }
+
+        Difficulty::from_name(&song.difficulty)?;
+        let modifiers = song
+            .gameplay_modifiers
+            .as_ref()
+            .ok_or_else(|| CommandError::InvalidPacket("Missing gameplay modifiers".into()))?;
+        let length = parse_duration(&level.duration)?;
+
+        let mut playing = self.playing.lock().unwrap();
+        if playing.is_some() {
+            return Err(CommandError::NotInMenu);
+        }
+
+        let now_playing = NowPlaying {
+            level_id: level.level_id.clone(),
+            is_finished: false,
+        };
+        println!("Playing {} on {}", level.level_id, song.difficulty);
+        let _ = self.events.send(now_playing.clone().into());
+
+        let speed = speed_multiplier(modifiers.song_speed());
+        let task = tokio::spawn(self.clone().progress(length, speed));
+        *playing = Some(Playing { now_playing, task });
+
+        Ok(())
+    }
+
+    /// Sends an update every [`UPDATE_INTERVAL`] until the level is over
+    async fn progress(self: Arc<Self>, length: f32, speed: f32) {
+        let mut interval = tokio::time::interval(UPDATE_INTERVAL.div_f32(self.time_scale));
+        // the first tick is immediate
+        interval.tick().await;
+
+        let mut elapsed = 0.0;
+        while elapsed < length {
+            interval.tick().await;
+            elapsed = f32::min(elapsed + speed, length);
+
+            let update = NowPlayingUpdate {
+                score: (elapsed * SCORE_PER_SECOND) as i32,
+                accuracy: ACCURACY,
+                elapsed: elapsed as i32,
+                total_time: length as i32,
+            };
+            let _ = self.events.send(update.into());
+        }
+
+        self.leave_level();
+    }
+
+    /// Quits the level being played, if any
+    pub fn return_to_menu(&self) {
+        if let Some(playing) = self.leave_level() {
+            playing.task.abort();
+        }
+    }
+
+    /// Like the game's `Finish`, which runs whether the level was completed
+    /// or quit
+    fn leave_level(&self) -> Option<Playing> {
+        let playing = self.playing.lock().unwrap().take()?;
+
+        let finished = NowPlaying {
+            is_finished: true,
+            ..playing.now_playing.clone()
+        };
+        println!("Left {}", finished.level_id);
+        let _ = self.events.send(finished.into());
+
+        Some(playing)
+    }
+}
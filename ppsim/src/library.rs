@@ -0,0 +1,68 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::{bail, Context};
+use party_panel_protocol::{proto::items::PreviewBeatmapLevel, schema::parse_duration};
+
+/// Played when no `--library` is given
+const BUILTIN: &str = include_str!("../fixtures/songs.json");
+
+/// A fixture file holds one level or a list of them
+fn parse(json: &str) -> anyhow::Result<Vec<PreviewBeatmapLevel>> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    if value.is_array() {
+        Ok(serde_json::from_value(value)?)
+    } else {
+        Ok(vec![serde_json::from_value(value)?])
+    }
+}
+
+/// Levels as the mod would send them in a `SongList`, from a JSON file, every
+/// `*.json` file in a directory, or the built-in fixture
+pub fn load(path: Option<&Path>) -> anyhow::Result<Vec<PreviewBeatmapLevel>> {
+    let levels = match path {
+        None => parse(BUILTIN).context("Built-in library is broken")?,
+        Some(path) if path.is_dir() => {
+            let mut files = fs::read_dir(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            files.retain(|file| file.extension().is_some_and(|ext| ext == "json"));
+            files.sort();
+
+            let mut levels = Vec::new();
+            for file in files {
+                let json = fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                levels.extend(parse(&json).with_context(|| format!("In {}", file.display()))?);
+            }
+            levels
+        }
+        Some(path) => {
+            let json = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            parse(&json).with_context(|| format!("In {}", path.display()))?
+        }
+    };
+
+    validate(&levels)?;
+    Ok(levels)
+}
+
+/// Catches fixtures the simulator couldn't play
+fn validate(levels: &[PreviewBeatmapLevel]) -> anyhow::Result<()> {
+    let mut ids = HashSet::new();
+
+    for level in levels {
+        if level.level_id.is_empty() {
+            bail!("Level {:?} has no level_id", level.name);
+        }
+        if !ids.insert(&level.level_id) {
+            bail!("Level {} is in the library twice", level.level_id);
+        }
+        if parse_duration(&level.duration)? <= 0.0 {
+            bail!("Level {} needs a duration to finish", level.level_id);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+mod args;
+mod devices;
+mod discovery;
+mod game;
+mod library;
+mod session;
+
+use std::{process::ExitCode, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use args::{Args, Target};
+use game::Game;
+use party_panel_protocol::{codec::PartyPanelCodec, transport::Transport};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Reported where the mod reports its own version
+const MOD_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Reported where the mod reports the game's version
+const GAME_VERSION: &str = "ppsim";
+
+/// How long to wait before dialing a panel again
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// What the simulated headset tells panels about itself
+pub struct Config {
+    pub name: String,
+    pub auth_token: Option<String>,
+    /// `None` in connect mode
+    pub listen_port: Option<u16>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", args::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("ppsim: {e:#}\n\n{}", args::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ppsim: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn spawn_discovery(port: u16, config: &Arc<Config>) {
+    if port == 0 {
+        return;
+    }
+
+    let config = config.clone();
+    tokio::spawn(async move {
+        if let Err(e) = discovery::run(port, config).await {
+            eprintln!("Discovery stopped: {e:#}");
+        }
+    });
+}
+
+/// Puts the transport panels expect on top of a connection.
+/// `ws_url` is set when ppsim dialed out and is the WebSocket client.
+async fn wrap(
+    stream: TcpStream,
+    websocket: bool,
+    ws_url: Option<String>,
+) -> anyhow::Result<Transport> {
+    stream.set_nodelay(true)?;
+    let codec = PartyPanelCodec::new();
+
+    if !websocket {
+        return Ok(Transport::from_io(stream, codec));
+    }
+    let ws = match ws_url {
+        Some(url) => tokio_tungstenite::client_async(url, stream)
+            .await
+            .map(|(ws, _)| ws),
+        None => tokio_tungstenite::accept_async(stream).await,
+    }
+    .context("WebSocket handshake failed")?;
+
+    Ok(Transport::from_websocket(ws, codec))
+}
+
+async fn run(args: Args) -> anyhow::Result<()> {
+    let levels = library::load(args.library.as_deref())?;
+    println!("Loaded {} levels", levels.len());
+    let game = Game::new(levels, args.time_scale);
+
+    match args.target {
+        Target::Listen(addr) => {
+            let listener = TcpListener::bind(&addr)
+                .await
+                .with_context(|| format!("Failed to listen on {addr}"))?;
+            let local_addr = listener.local_addr()?;
+            let config = Arc::new(Config {
+                name: args.name,
+                auth_token: args.token,
+                listen_port: Some(local_addr.port()),
+            });
+            spawn_discovery(args.discovery_port, &config);
+            println!("Waiting for panels on {local_addr}");
+
+            loop {
+                let (stream, peer) = listener.accept().await?;
+
+                let game = game.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let result = async {
+                        let socket = wrap(stream, args.websocket, None).await?;
+                        session::run(socket, game, &config).await
+                    };
+                    if let Err(e) = result.await {
+                        eprintln!("Panel {peer}: {e:#}");
+                    }
+                });
+            }
+        }
+        Target::Connect(addr) => {
+            let config = Arc::new(Config {
+                name: args.name,
+                auth_token: args.token,
+                listen_port: None,
+            });
+            spawn_discovery(args.discovery_port, &config);
+
+            loop {
+                match TcpStream::connect(&addr).await {
+                    Ok(stream) => {
+                        let ws_url = format!("ws://{addr}/");
+                        let result = async {
+                            let socket = wrap(stream, args.websocket, Some(ws_url)).await?;
+                            session::run(socket, game.clone(), &config).await
+                        };
+                        if let Err(e) = result.await {
+                            eprintln!("Panel {addr}: {e:#}");
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to connect to {addr}: {e}"),
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
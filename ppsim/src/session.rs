@@ -0,0 +1,395 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{SinkExt, StreamExt};
+use party_panel_protocol::{
+    capabilities::{
+        self, COMPRESSION_CAPABILITY, HEARTBEAT_CAPABILITY, SONG_LIST_DELTAS_CAPABILITY,
+        SONG_LIST_PAGES_CAPABILITY,
+    },
+    error::{ack, CommandError, ProtocolError},
+    handshake::{self, Gatekeeper, Handshake},
+    heartbeat::{self, Heartbeat},
+    pairing::PIN_TTL,
+    proto::{
+        items::PreviewBeatmapLevel,
+        packets::{
+            error_event::{Severity, Source},
+            DeviceList, ErrorEvent, Paired, PairingPin, PlaySong, SongList, SongListBegin,
+            SongListEnd, SongListPage,
+        },
+        CommandType, Packet,
+    },
+    transport::Transport,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{devices::DEVICES, game::Game, Config};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Levels per `SongListPage`, the same as the mod
+const SONG_LIST_PAGE_SIZE: usize = 200;
+
+/// Fixtures never change, so every list is the same revision
+const LIBRARY_REVISION: u64 = 1;
+
+/// A restarted simulator is a new library all the same, like a restarted mod
+static LIBRARY_EPOCH: LazyLock<u64> = LazyLock::new(|| {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_nanos() as u64)
+});
+
+/// Panels get in like they do on the mod, with `--token` or as a device
+/// paired with this ppsim
+struct Gate<'a> {
+    auth_token: Option<&'a str>,
+}
+
+impl Gatekeeper for Gate<'_> {
+    fn auth_required(&self) -> bool {
+        self.auth_token.is_some() || !DEVICES.is_empty()
+    }
+
+    fn secret(&self, device_id: &str) -> Option<String> {
+        if device_id.is_empty() {
+            self.auth_token.map(str::to_string)
+        } else {
+            DEVICES.secret(device_id)
+        }
+    }
+
+    async fn pair(
+        &self,
+        request_id: u32,
+        pin: &str,
+        device_name: String,
+    ) -> Result<Paired, CommandError> {
+        DEVICES.pair(request_id, pin, device_name)
+    }
+}
+
+/// The whole library, in pages if the panel takes them
+async fn send_song_list(
+    socket: &mut Transport,
+    levels: &[PreviewBeatmapLevel],
+    pages: bool,
+) -> anyhow::Result<()> {
+    if !pages {
+        let list = SongList {
+            levels: levels.to_vec(),
+        };
+        socket.send(list.into()).await?;
+        return Ok(());
+    }
+
+    let begin = SongListBegin {
+        list_id: LIBRARY_REVISION,
+        total_levels: levels.len() as u32,
+        page_size: SONG_LIST_PAGE_SIZE as u32,
+    };
+    socket.feed(begin.into()).await?;
+
+    let mut processed_levels = 0;
+    let chunks = levels.chunks(SONG_LIST_PAGE_SIZE);
+    let pages = chunks.len();
+    for (page, chunk) in chunks.enumerate() {
+        processed_levels += chunk.len() as u32;
+        let page = SongListPage {
+            list_id: LIBRARY_REVISION,
+            page: page as u32,
+            levels: chunk.to_vec(),
+            errors: Vec::new(),
+            processed_levels,
+        };
+        socket.feed(page.into()).await?;
+    }
+
+    let end = SongListEnd {
+        list_id: LIBRARY_REVISION,
+        pages: pages as u32,
+        levels: levels.len() as u32,
+        failed_levels: 0,
+        revision: LIBRARY_REVISION,
+        library_epoch: *LIBRARY_EPOCH,
+    };
+    socket.send(end.into()).await?;
+
+    Ok(())
+}
+
+/// Carries out a packet and tells the panel how it went, in an `Ack` if it
+/// asked for one and an `ErrorEvent` otherwise
+async fn handle(
+    socket: &mut Transport,
+    game: &Arc<Game>,
+    pages: bool,
+    packet: Packet,
+) -> anyhow::Result<()> {
+    let request_id = packet.request_id();
+
+    let result = match packet {
+        Packet::PlaySong(song) => game.play(&song),
+        Packet::PlaySongV2(song) => PlaySong::try_from(song)
+            .map_err(CommandError::from)
+            .and_then(|song| game.play(&song)),
+        Packet::Command(command) => match CommandType::try_from(command.command_type) {
+            Ok(CommandType::ReturnToMenu) => {
+                game.return_to_menu();
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(CommandError::InvalidPacket(e.to_string())),
+        },
+        Packet::SyncSongs(sync) => {
+            if sync.library_epoch != *LIBRARY_EPOCH || sync.since_revision != LIBRARY_REVISION {
+                send_song_list(socket, game.levels(), pages).await?;
+            }
+            Ok(())
+        }
+        Packet::StartPairing(_) => {
+            let pin = DEVICES.start_pairing();
+            let pin = PairingPin {
+                request_id,
+                pin,
+                expires_in_secs: PIN_TTL.as_secs() as u32,
+            };
+            socket.send(pin.into()).await?;
+            return Ok(());
+        }
+        Packet::Pair(pair) => match DEVICES.pair(request_id, &pair.pin, pair.device_name) {
+            Ok(paired) => {
+                socket.send(paired.into()).await?;
+                return Ok(());
+            }
+            Err(e) => Err(e),
+        },
+        Packet::ListDevices(_) => {
+            let devices = DeviceList {
+                request_id,
+                devices: DEVICES.list(),
+            };
+            socket.send(devices.into()).await?;
+            return Ok(());
+        }
+        Packet::RevokeDevice(revoke) => DEVICES.revoke(&revoke.device_id),
+        packet => Err(CommandError::Unsupported(packet.get_type())),
+    };
+
+    if request_id != 0 {
+        socket
+            .send(ack(request_id, result.as_ref().copied()).into())
+            .await?;
+    } else if let Err(e) = result {
+        let event = ErrorEvent {
+            source: Source::Command as i32,
+            severity: Severity::Error as i32,
+            message: e.to_string(),
+            level_id: String::new(),
+        };
+        socket.send(event.into()).await?;
+    }
+
+    Ok(())
+}
+
+/// Serves one panel like the mod's session: handshake, song list, what is
+/// playing, then packets both ways until it disconnects
+pub async fn run(mut socket: Transport, game: Arc<Game>, config: &Config) -> anyhow::Result<()> {
+    let welcome = handshake::welcome(
+        crate::MOD_VERSION.to_string(),
+        crate::GAME_VERSION.to_string(),
+        String::new(),
+    );
+    let gate = Gate {
+        auth_token: config.auth_token.as_deref(),
+    };
+    // subscribed before the handshake, so a revocation right after it isn't missed
+    let mut revoked = DEVICES.subscribe_revoked();
+    let (name, capabilities, device_id, first_packet) =
+        match handshake::accept(&mut socket, welcome, &gate).await? {
+            Handshake::Accepted { hello, device_id } => (
+                hello.client_name,
+                capabilities::effective(hello.capabilities),
+                device_id,
+                None,
+            ),
+            Handshake::Legacy(packet) => ("legacy panel".to_string(), Vec::new(), None, packet),
+        };
+    println!("{name} connected");
+
+    let supports = |capability: &str| capabilities.iter().any(|c| c == capability);
+    socket
+        .codec
+        .set_compression(supports(COMPRESSION_CAPABILITY));
+    let heartbeats = supports(HEARTBEAT_CAPABILITY);
+    let pages = supports(SONG_LIST_PAGES_CAPABILITY);
+    // panels taking deltas ask with SyncSongs
+    let deltas = supports(SONG_LIST_DELTAS_CAPABILITY);
+
+    let mut events = game.subscribe();
+    if !deltas {
+        send_song_list(&mut socket, game.levels(), pages).await?;
+    }
+    if let Some(now_playing) = game.now_playing() {
+        socket.send(now_playing.into()).await?;
+    }
+    if let Some(packet) = first_packet {
+        handle(&mut socket, &game, pages, packet).await?;
+    }
+
+    let mut heartbeat = Heartbeat::new(HEARTBEAT_INTERVAL, Some(HEARTBEAT_TIMEOUT));
+    let mut ticker = tokio::time::interval(heartbeat.interval());
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let deadline = heartbeat.deadline().filter(|_| heartbeats);
+        // not polled without a deadline
+        let quiet = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now));
+
+        let packet = tokio::select! {
+            packet = socket.next() => packet,
+            event = events.recv() => {
+                match event {
+                    Ok(packet) => socket.send(packet).await?,
+                    Err(RecvError::Lagged(missed)) => println!("{name} missed {missed} packets"),
+                    Err(RecvError::Closed) => break,
+                }
+                continue;
+            }
+            _ = revoked.recv(), if device_id.is_some() => {
+                // lagging behind is no reason to keep a revoked device around
+                if device_id.as_deref().and_then(|id| DEVICES.secret(id)).is_none() {
+                    println!("{name} was revoked");
+                    break;
+                }
+                continue;
+            }
+            _ = ticker.tick(), if heartbeats => {
+                socket.send(heartbeat.ping().into()).await?;
+                continue;
+            }
+            _ = quiet, if deadline.is_some() => {
+                return Err(ProtocolError::PeerTimeout(HEARTBEAT_TIMEOUT).into());
+            }
+        };
+        let Some(packet) = packet else {
+            break;
+        };
+        let packet = packet?;
+        heartbeat.received();
+
+        if let Packet::Command(command) = &packet {
+            if heartbeat::is_heartbeat(command) {
+                if let Some(echo) = heartbeat.handle(command) {
+                    socket.send(echo.into()).await?;
+                }
+                continue;
+            }
+        }
+
+        handle(&mut socket, &game, pages, packet).await?;
+    }
+
+    println!("{name} disconnected");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use party_panel_client::{ClientError, ClientOptions, Credentials, PartyPanelClient};
+    use party_panel_protocol::{
+        codec::PartyPanelCodec,
+        proto::{
+            items::GameplayModifiers,
+            packets::ack::ErrorCode,
+            v2::{self, CharacteristicKind, Difficulty},
+        },
+    };
+
+    use super::*;
+    use crate::library;
+
+    const TOKEN: &str = "correct horse";
+
+    /// A client handshaking with a ppsim session over an in-memory pipe
+    async fn connect(
+        credentials: Option<Credentials>,
+    ) -> (Result<PartyPanelClient, ClientError>, Arc<Game>) {
+        let game = Game::new(library::load(None).unwrap(), 1.0);
+        let config = Config {
+            name: "ppsim".to_string(),
+            auth_token: Some(TOKEN.to_string()),
+            listen_port: None,
+        };
+
+        let (panel, headset) = tokio::io::duplex(64 * 1024);
+        let socket = Transport::from_io(headset, PartyPanelCodec::new());
+        let session_game = game.clone();
+        tokio::spawn(async move { run(socket, session_game, &config).await });
+
+        let options = ClientOptions {
+            credentials,
+            ..Default::default()
+        };
+        (PartyPanelClient::handshake(panel, options).await, game)
+    }
+
+    #[tokio::test]
+    async fn serves_the_library() {
+        let (client, game) = connect(Some(Credentials::Token(TOKEN.to_string()))).await;
+        let client = client.unwrap();
+
+        assert!(client.welcome().accepted);
+        assert_eq!(client.songs().await.unwrap(), game.levels());
+    }
+
+    #[tokio::test]
+    async fn plays_songs() {
+        let (client, game) = connect(Some(Credentials::Token(TOKEN.to_string()))).await;
+        let client = client.unwrap();
+        let level_id = game.levels()[0].level_id.clone();
+
+        let song = v2::PlaySong {
+            level_id: level_id.clone(),
+            difficulty: Difficulty::Expert as i32,
+            characteristic: Some(v2::Characteristic {
+                kind: CharacteristicKind::Standard as i32,
+                ..Default::default()
+            }),
+            gameplay_modifiers: Some(GameplayModifiers::default()),
+            request_id: 0,
+        };
+        client.play_song(song).await.unwrap();
+        assert_eq!(game.now_playing().unwrap().level_id, level_id);
+
+        let missing = v2::PlaySong {
+            level_id: "missing".to_string(),
+            difficulty: Difficulty::Expert as i32,
+            characteristic: Some(v2::Characteristic {
+                kind: CharacteristicKind::Standard as i32,
+                ..Default::default()
+            }),
+            gameplay_modifiers: Some(GameplayModifiers::default()),
+            request_id: 0,
+        };
+        let Err(ClientError::Rejected { code, .. }) = client.play_song(missing).await else {
+            panic!("expected the missing level to be rejected");
+        };
+        assert_eq!(code, ErrorCode::LevelNotFound);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_token() {
+        let (client, _) = connect(Some(Credentials::Token("wrong".to_string()))).await;
+
+        let Err(ClientError::Rejected { code, .. }) = client else {
+            panic!("expected the wrong token to be rejected");
+        };
+        assert_eq!(code, ErrorCode::Unauthorized);
+    }
+}
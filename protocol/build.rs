@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // serde lets the same messages be sent as JSON, see codec::Encoding
+    prost_build::Config::new()
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".", "#[serde(default)]")
+        .compile_protos(
+            &["src/items.proto", "src/packets.proto", "src/v2.proto"],
+            &["src/"],
+        )?;
+
+    Ok(())
+}
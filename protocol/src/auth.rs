@@ -0,0 +1,39 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Random bytes the panel has to sign, sent in `Welcome.auth_challenge`
+pub const CHALLENGE_LEN: usize = 32;
+
+/// A fresh challenge, different for every connection so responses can't be replayed
+pub fn challenge() -> Vec<u8> {
+    let mut challenge = vec![0; CHALLENGE_LEN];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    challenge
+}
+
+fn mac(token: &str, challenge: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(challenge);
+    mac
+}
+
+/// What a panel holding `token` sends back in `Authenticate.response`
+pub fn response(token: &str, challenge: &[u8]) -> Vec<u8> {
+    mac(token, challenge).finalize().into_bytes().to_vec()
+}
+
+/// Checks a panel's response in constant time
+pub fn verify(token: &str, challenge: &[u8], response: &[u8]) -> bool {
+    mac(token, challenge).verify_slice(response).is_ok()
+}
+
+/// Compares a secret a panel sent, like a pairing PIN, in constant time
+pub fn secrets_match(expected: &str, sent: &str) -> bool {
+    // keyed with each, so they only MAC the same if they are the same
+    const CONTEXT: &[u8] = b"party_panel secret";
+    verify(expected, CONTEXT, &response(sent, CONTEXT))
+}
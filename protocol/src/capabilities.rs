@@ -0,0 +1,30 @@
+/// JSON lines instead of protobuf frames, see [`crate::codec::Encoding`]
+pub const JSON_CAPABILITY: &str = "json";
+
+/// Deflating large frames, used once both sides advertise it
+pub const COMPRESSION_CAPABILITY: &str = "deflate";
+
+/// Song lists sent as `SongListBegin`/`SongListPage`/`SongListEnd` instead of
+/// one `SongList`, for panels that advertise it
+pub const SONG_LIST_PAGES_CAPABILITY: &str = "song_list_pages";
+
+/// Library changes sent as `SongsAdded`/`SongsRemoved`/`SongsChanged` instead
+/// of the whole list on every refresh, for panels that advertise it. Such
+/// panels send `SyncSongs` once connected rather than being sent the list.
+///
+/// Only used together with [`SONG_LIST_PAGES_CAPABILITY`], since the revision
+/// deltas build on comes in `SongListEnd`, see [`effective`].
+pub const SONG_LIST_DELTAS_CAPABILITY: &str = "song_list_deltas";
+
+/// Heartbeats in both directions, and disconnecting when the panel goes quiet.
+/// Panels without it are never timed out since they might not send anything.
+pub const HEARTBEAT_CAPABILITY: &str = "heartbeat";
+
+/// What a panel advertised, minus capabilities that can't work without one
+/// it left out
+pub fn effective(mut capabilities: Vec<String>) -> Vec<String> {
+    if !capabilities.iter().any(|c| c == SONG_LIST_PAGES_CAPABILITY) {
+        capabilities.retain(|c| c != SONG_LIST_DELTAS_CAPABILITY);
+    }
+    capabilities
+}
@@ -0,0 +1,836 @@
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::warn;
+
+use crate::{
+    error::ProtocolError,
+    proto::{packets, Packet, PacketType},
+};
+
+/// UTF8 "moon", written before every packet
+pub const MAGIC: &[u8; 4] = b"moon";
+
+/// magic + i32 packet type + u64 body length
+pub const HEADER_LEN: usize = MAGIC.len() + size_of::<i32>() + size_of::<u64>();
+
+/// Song lists for large custom libraries can get big, but anything past this is
+/// almost certainly garbage
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+
+/// How many bytes we are willing to throw away looking for the next "moon"
+/// before giving up on the connection
+pub const MAX_RESYNC_BYTES: usize = 64 * 1024;
+
+/// Set in the packet type of frames whose body is deflate compressed
+pub const COMPRESSED_FLAG: i32 = 1 << 30;
+
+/// Bodies smaller than this aren't worth compressing
+pub const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// Packet type of a frame holding a [`packets::Packet`] envelope
+pub const ENVELOPE_PACKET_TYPE: i32 = 0xFFFF;
+
+/// How protobuf packets are framed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// The message's [`PacketType`] in the header, protocol version 1
+    #[default]
+    Legacy,
+    /// [`ENVELOPE_PACKET_TYPE`] in the header and the message wrapped in a
+    /// [`packets::Packet`], protocol version 2
+    Envelope,
+}
+
+/// How packets are laid out on a connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// "moon" frames with protobuf bodies
+    #[default]
+    Protobuf,
+    /// One JSON [`Packet`] per line, for scripts and debugging.
+    /// Enum fields are sent as their protobuf numbers.
+    Json,
+}
+
+/// Encodes and decodes the "moon" framing used between the mod and the panel.
+///
+/// Every frame is laid out as:
+/// ```text
+/// "moon" | packet type (i32, big endian) | body length (u64, big endian) | protobuf body
+/// ```
+///
+/// Use with [`tokio_util::codec::Framed`] (or `FramedRead`/`FramedWrite`) over any
+/// `AsyncRead`/`AsyncWrite`.
+///
+/// Frames with a packet type we don't know about are skipped, so newer panels can
+/// talk to older mods. A bad header resynchronises on the next "moon", and the
+/// decoder only errors once [`MAX_RESYNC_BYTES`] have been discarded.
+///
+/// Unless set with [`PartyPanelCodec::with_encoding`], the [`Encoding`] is picked
+/// from the first thing the peer sends: a `{` means JSON lines, anything else
+/// protobuf. Clones share the choice, so the writing half of a connection
+/// answers in whatever the reading half detected.
+///
+/// Protobuf frames are read with either [`Framing`] and written with whichever
+/// the peer used first, the same way.
+///
+/// Protobuf bodies over [`COMPRESSION_THRESHOLD`] are deflated once compression
+/// has been negotiated, with [`COMPRESSED_FLAG`] set in the packet type.
+/// Compressed frames are always accepted.
+#[derive(Clone, Debug)]
+pub struct PartyPanelCodec {
+    max_frame_size: usize,
+    /// bytes discarded since the last valid header
+    skipped: usize,
+    negotiated: Arc<Negotiated>,
+}
+
+/// Settings picked per connection, shared by every clone of a codec
+#[derive(Debug, Default)]
+struct Negotiated {
+    encoding: OnceLock<Encoding>,
+    framing: OnceLock<Framing>,
+    compress: AtomicBool,
+}
+
+impl Default for PartyPanelCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartyPanelCodec {
+    pub fn new() -> Self {
+        Self::with_max_frame_size(DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            skipped: 0,
+            negotiated: Arc::default(),
+        }
+    }
+
+    /// Skips detection, for the side of a connection that speaks first
+    pub fn with_encoding(self, encoding: Encoding) -> Self {
+        let _ = self.negotiated.encoding.set(encoding);
+        self
+    }
+
+    /// Skips framing detection, for the side of a connection that speaks first
+    pub fn with_framing(self, framing: Framing) -> Self {
+        let _ = self.negotiated.framing.set(framing);
+        self
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// The encoding used on this connection, `None` until the peer has sent something
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.negotiated.encoding.get().copied()
+    }
+
+    /// The framing used on this connection, `None` until the peer has sent a protobuf frame
+    pub fn framing(&self) -> Option<Framing> {
+        self.negotiated.framing.get().copied()
+    }
+
+    /// Starts compressing large frames, once the peer has said it can inflate them
+    pub fn set_compression(&self, enabled: bool) {
+        self.negotiated.compress.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn compression(&self) -> bool {
+        self.negotiated.compress.load(Ordering::Relaxed)
+    }
+
+    /// Inflates a compressed body, refusing to grow past the frame size limit
+    fn inflate(&self, packet_type: i32, body: Bytes) -> Result<Bytes, ProtocolError> {
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(body.reader())
+            .take(self.max_frame_size as u64 + 1)
+            .read_to_end(&mut inflated)
+            .map_err(|source| ProtocolError::Decompress {
+                packet_type,
+                source,
+            })?;
+
+        if inflated.len() > self.max_frame_size {
+            return Err(ProtocolError::FrameTooLarge {
+                len: inflated.len() as u64,
+                max: self.max_frame_size,
+            });
+        }
+
+        Ok(inflated.into())
+    }
+
+    /// Drops bytes until `src` starts with "moon" or could still be the start of one.
+    /// Returns `true` if a full magic is now at the front of the buffer.
+    fn resync(&mut self, src: &mut BytesMut) -> Result<bool, ProtocolError> {
+        let discard = src
+            .windows(MAGIC.len())
+            .position(|window| window == MAGIC)
+            // keep a possible partial "moon" at the end of the buffer
+            .unwrap_or_else(|| src.len().saturating_sub(MAGIC.len() - 1));
+
+        src.advance(discard);
+        self.skipped += discard;
+
+        if self.skipped > MAX_RESYNC_BYTES {
+            return Err(ProtocolError::BadMagic {
+                skipped: self.skipped,
+            });
+        }
+
+        Ok(src.starts_with(MAGIC))
+    }
+
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, ProtocolError> {
+        loop {
+            if !src.starts_with(MAGIC) && src.len() >= MAGIC.len() && !self.resync(src)? {
+                return Ok(None);
+            }
+
+            if src.len() < HEADER_LEN {
+                src.reserve(HEADER_LEN - src.len());
+                return Ok(None);
+            }
+
+            if self.skipped > 0 {
+                warn!("Skipped {} bytes of garbage before header", self.skipped);
+                self.skipped = 0;
+            }
+
+            let mut header = &src[MAGIC.len()..HEADER_LEN];
+            let packet_type = header.get_i32();
+            let compressed = packet_type & COMPRESSED_FLAG != 0;
+            let packet_type = packet_type & !COMPRESSED_FLAG;
+            let len = header.get_u64();
+
+            // check before allocating anything for the body
+            if len > self.max_frame_size as u64 {
+                return Err(ProtocolError::FrameTooLarge {
+                    len,
+                    max: self.max_frame_size,
+                });
+            }
+            let len = len as usize;
+
+            let frame_len = HEADER_LEN + len;
+            if src.len() < frame_len {
+                // wait for the rest of the body
+                src.reserve(frame_len - src.len());
+                return Ok(None);
+            }
+
+            src.advance(HEADER_LEN);
+            let mut body = src.split_to(len).freeze();
+
+            let framing = if packet_type == ENVELOPE_PACKET_TYPE {
+                Framing::Envelope
+            } else {
+                Framing::Legacy
+            };
+            self.negotiated.framing.get_or_init(|| framing);
+
+            if framing == Framing::Envelope {
+                if compressed {
+                    body = self.inflate(packet_type, body)?;
+                }
+
+                let envelope = <packets::Packet as prost::Message>::decode(body)
+                    .map_err(ProtocolError::DecodeEnvelope)?;
+                match Packet::try_from(envelope) {
+                    Ok(packet) => return Ok(Some(packet)),
+                    Err(e) => {
+                        warn!("Skipping packet: {e}");
+                        continue;
+                    }
+                }
+            }
+
+            let packet_type = match PacketType::try_from(packet_type) {
+                Ok(packet_type) => packet_type,
+                Err(e) => {
+                    // well framed, just newer than us
+                    warn!("Skipping packet: {e}");
+                    continue;
+                }
+            };
+
+            if compressed {
+                body = self.inflate(packet_type as i32, body)?;
+            }
+
+            let packet =
+                Packet::decode(packet_type, body).map_err(|source| ProtocolError::Decode {
+                    packet_type,
+                    source,
+                })?;
+
+            return Ok(Some(packet));
+        }
+    }
+
+    /// Writes one "moon" frame, deflating the body if it is worth it
+    fn encode_frame(
+        &self,
+        packet_type: i32,
+        len: usize,
+        encode_body: impl Fn(&mut BytesMut) -> Result<(), prost::EncodeError>,
+        dst: &mut BytesMut,
+    ) -> Result<(), ProtocolError> {
+        if self.compression() && len > COMPRESSION_THRESHOLD {
+            let mut body = BytesMut::with_capacity(len);
+            encode_body(&mut body)?;
+
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&body)?;
+            let compressed = encoder.finish()?;
+
+            // already compressed data (covers) can come out bigger
+            if compressed.len() < len {
+                dst.reserve(HEADER_LEN + compressed.len());
+                dst.put_slice(MAGIC);
+                dst.put_i32(packet_type | COMPRESSED_FLAG);
+                dst.put_u64(compressed.len() as u64);
+                dst.put_slice(&compressed);
+                return Ok(());
+            }
+        }
+
+        dst.reserve(HEADER_LEN + len);
+
+        dst.put_slice(MAGIC);
+        dst.put_i32(packet_type);
+        dst.put_u64(len as u64);
+        encode_body(dst)?;
+
+        Ok(())
+    }
+
+    fn decode_json(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, ProtocolError> {
+        loop {
+            let Some(end) = src.iter().position(|b| *b == b'\n') else {
+                if src.len() > self.max_frame_size {
+                    return Err(ProtocolError::FrameTooLarge {
+                        len: src.len() as u64,
+                        max: self.max_frame_size,
+                    });
+                }
+                return Ok(None);
+            };
+
+            let line = src.split_to(end + 1);
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+
+            match serde_json::from_slice(line) {
+                Ok(packet) => return Ok(Some(packet)),
+                // a typo or a packet from a newer panel, the next line is unaffected
+                Err(e) => warn!("Skipping JSON packet: {e}"),
+            }
+        }
+    }
+}
+
+impl Decoder for PartyPanelCodec {
+    type Item = Packet;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let encoding = match self.encoding() {
+            Some(encoding) => encoding,
+            None => {
+                let Some(first) = src.iter().find(|b| !b.is_ascii_whitespace()) else {
+                    return Ok(None);
+                };
+
+                let detected = if *first == b'{' {
+                    Encoding::Json
+                } else {
+                    Encoding::Protobuf
+                };
+                *self.negotiated.encoding.get_or_init(|| detected)
+            }
+        };
+
+        match encoding {
+            Encoding::Protobuf => self.decode_frame(src),
+            Encoding::Json => self.decode_json(src),
+        }
+    }
+}
+
+impl Encoder<Packet> for PartyPanelCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.encoding().unwrap_or_default() {
+            Encoding::Protobuf => match self.framing().unwrap_or_default() {
+                Framing::Legacy => self.encode_frame(
+                    item.get_type() as i32,
+                    item.encoded_len(),
+                    |buf| item.encode_body(buf),
+                    dst,
+                )?,
+                Framing::Envelope => {
+                    use prost::Message;
+
+                    let envelope = packets::Packet::from(item);
+                    self.encode_frame(
+                        ENVELOPE_PACKET_TYPE,
+                        envelope.encoded_len(),
+                        |buf| envelope.encode(buf),
+                        dst,
+                    )?
+                }
+            },
+            Encoding::Json => {
+                serde_json::to_writer(dst.writer(), &item)?;
+                dst.put_u8(b'\n');
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{items, v2, CommandType};
+
+    fn level(n: usize) -> items::PreviewBeatmapLevel {
+        items::PreviewBeatmapLevel {
+            level_id: format!("custom_level_{n:040}"),
+            name: format!("Level {n}"),
+            author: "Someone".to_string(),
+            duration: "3:05".to_string(),
+            bpm: 128.5,
+            cover: vec![n as u8; 4],
+            chars: vec![items::Characteristic {
+                name: "Standard".to_string(),
+                diffs: vec!["Expert".to_string(), "ExpertPlus".to_string()],
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// One of every packet, with something set in each
+    fn every_packet() -> Vec<Packet> {
+        vec![
+            packets::SongList {
+                levels: vec![level(0), level(1)],
+            }
+            .into(),
+            packets::Command {
+                command_type: CommandType::Heartbeat as i32,
+                request_id: 1,
+                heartbeat_id: 2,
+                heartbeat_reply: true,
+                round_trip_ms: 3,
+            }
+            .into(),
+            packets::NowPlaying {
+                level_id: "level".to_string(),
+                is_finished: true,
+            }
+            .into(),
+            packets::NowPlayingUpdate {
+                score: 1234,
+                accuracy: 0.75,
+                elapsed: 30,
+                total_time: 185,
+            }
+            .into(),
+            packets::PlaySong {
+                level_id: "level".to_string(),
+                difficulty: "Expert".to_string(),
+                characteristic: level(0).chars.pop(),
+                gameplay_modifiers: Some(items::GameplayModifiers {
+                    no_fail_on_0_energy: true,
+                    ..Default::default()
+                }),
+                request_id: 4,
+            }
+            .into(),
+            packets::PreviewSong {
+                level: Some(level(2)),
+            }
+            .into(),
+            packets::DownloadSong {
+                level_id: "level".to_string(),
+                song_key: "1a2b".to_string(),
+                request_id: 5,
+            }
+            .into(),
+            packets::AllSongs {
+                lists: vec![packets::SongList {
+                    levels: vec![level(3)],
+                }],
+            }
+            .into(),
+            packets::Hello {
+                protocol_version: 2,
+                client_name: "panel".to_string(),
+                client_version: "1.0".to_string(),
+                supported_packets: vec![0, 1, 2],
+                capabilities: vec!["compression".to_string()],
+            }
+            .into(),
+            packets::Welcome {
+                protocol_version: 2,
+                mod_version: "0.1.0".to_string(),
+                accepted: true,
+                auth_challenge: vec![1, 2, 3],
+                ..Default::default()
+            }
+            .into(),
+            packets::Ack {
+                request_id: 6,
+                success: false,
+                error_code: packets::ack::ErrorCode::NotInMenu as i32,
+                message: "not in the menu".to_string(),
+            }
+            .into(),
+            packets::ErrorEvent {
+                source: packets::error_event::Source::SongList as i32,
+                severity: packets::error_event::Severity::Warning as i32,
+                message: "broken".to_string(),
+                level_id: "level".to_string(),
+            }
+            .into(),
+            packets::Authenticate {
+                response: vec![9; 32],
+                request_id: 7,
+                device_id: "device".to_string(),
+            }
+            .into(),
+            packets::StartPairing { request_id: 8 }.into(),
+            packets::PairingPin {
+                request_id: 9,
+                pin: "123456".to_string(),
+                expires_in_secs: 60,
+            }
+            .into(),
+            packets::Pair {
+                pin: "123456".to_string(),
+                device_name: "tablet".to_string(),
+                request_id: 10,
+            }
+            .into(),
+            packets::Paired {
+                request_id: 11,
+                device_id: "device".to_string(),
+                secret: "secret".to_string(),
+            }
+            .into(),
+            packets::ListDevices { request_id: 12 }.into(),
+            packets::DeviceList {
+                request_id: 13,
+                devices: vec![packets::device_list::Device {
+                    device_id: "device".to_string(),
+                    name: "tablet".to_string(),
+                    paired_at: 1_700_000_000,
+                }],
+            }
+            .into(),
+            packets::RevokeDevice {
+                device_id: "device".to_string(),
+                request_id: 14,
+            }
+            .into(),
+            packets::SongListBegin {
+                list_id: 1,
+                total_levels: 2,
+                page_size: 200,
+            }
+            .into(),
+            packets::SongListPage {
+                list_id: 1,
+                page: 0,
+                levels: vec![level(4)],
+                errors: vec![packets::LevelError {
+                    level_id: "level".to_string(),
+                    message: "broken".to_string(),
+                }],
+                processed_levels: 2,
+            }
+            .into(),
+            packets::SongListEnd {
+                list_id: 1,
+                pages: 1,
+                levels: 1,
+                failed_levels: 1,
+                revision: 1,
+                library_epoch: 42,
+            }
+            .into(),
+            packets::SongsAdded {
+                revision: 2,
+                previous_revision: 1,
+                levels: vec![level(5)],
+            }
+            .into(),
+            packets::SongsRemoved {
+                revision: 2,
+                previous_revision: 1,
+                level_ids: vec!["level".to_string()],
+            }
+            .into(),
+            packets::SongsChanged {
+                revision: 2,
+                previous_revision: 1,
+                levels: vec![level(6)],
+            }
+            .into(),
+            packets::SyncSongs {
+                since_revision: 1,
+                request_id: 15,
+                library_epoch: 42,
+            }
+            .into(),
+            packets::DiscoveryQuery {
+                protocol_version: 2,
+            }
+            .into(),
+            packets::DiscoveryAnnouncement {
+                name: "Quest".to_string(),
+                listening: true,
+                listen_port: 3502,
+                ..Default::default()
+            }
+            .into(),
+            v2::PlaySong {
+                level_id: "level".to_string(),
+                difficulty: v2::Difficulty::ExpertPlus as i32,
+                characteristic: Some(v2::Characteristic::from_serialized_name("Lawless")),
+                gameplay_modifiers: Some(items::GameplayModifiers::default()),
+                request_id: 16,
+            }
+            .into(),
+        ]
+    }
+
+    /// A big song list that deflates well
+    fn big_song_list() -> Packet {
+        packets::SongList {
+            levels: (0..1000).map(level).collect(),
+        }
+        .into()
+    }
+
+    fn encode(codec: &mut PartyPanelCodec, packets: &[Packet]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        for packet in packets {
+            codec.encode(packet.clone(), &mut buf).unwrap();
+        }
+        buf
+    }
+
+    fn decode_all(codec: &mut PartyPanelCodec, buf: &mut BytesMut) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        while let Some(packet) = codec.decode(buf).unwrap() {
+            packets.push(packet);
+        }
+        assert!(buf.is_empty(), "{} bytes left over", buf.len());
+        packets
+    }
+
+    fn round_trip(encoding: Encoding, framing: Framing) {
+        let packets = every_packet();
+        let mut encoder = PartyPanelCodec::new()
+            .with_encoding(encoding)
+            .with_framing(framing);
+        let mut buf = encode(&mut encoder, &packets);
+
+        // the reading side detects both
+        let mut decoder = PartyPanelCodec::new();
+        assert_eq!(decode_all(&mut decoder, &mut buf), packets);
+        assert_eq!(decoder.encoding(), Some(encoding));
+    }
+
+    #[test]
+    fn every_packet_type_is_covered() {
+        let types: Vec<_> = every_packet().iter().map(Packet::get_type).collect();
+        let all: Vec<_> = (0..)
+            .map_while(|value| PacketType::try_from(value).ok())
+            .collect();
+        assert_eq!(types, all);
+    }
+
+    #[test]
+    fn round_trips_legacy_frames() {
+        round_trip(Encoding::Protobuf, Framing::Legacy);
+    }
+
+    #[test]
+    fn round_trips_envelopes() {
+        round_trip(Encoding::Protobuf, Framing::Envelope);
+    }
+
+    #[test]
+    fn round_trips_json() {
+        round_trip(Encoding::Json, Framing::Legacy);
+    }
+
+    #[test]
+    fn detects_envelopes() {
+        let packet: Packet = packets::StartPairing { request_id: 3 }.into();
+
+        for framing in [Framing::Legacy, Framing::Envelope] {
+            let mut buf = encode(
+                &mut PartyPanelCodec::new().with_framing(framing),
+                std::slice::from_ref(&packet),
+            );
+            let expected_type = match framing {
+                Framing::Legacy => PacketType::StartPairing as i32,
+                Framing::Envelope => ENVELOPE_PACKET_TYPE,
+            };
+            assert_eq!((&buf[MAGIC.len()..]).get_i32(), expected_type);
+
+            let mut decoder = PartyPanelCodec::new();
+            assert_eq!(decoder.decode(&mut buf).unwrap(), Some(packet.clone()));
+            assert_eq!(decoder.framing(), Some(framing));
+
+            // and answers the same way
+            let reply = encode(&mut decoder.clone(), std::slice::from_ref(&packet));
+            assert_eq!((&reply[MAGIC.len()..]).get_i32(), expected_type);
+        }
+    }
+
+    #[test]
+    fn skips_unknown_packets() {
+        let packet: Packet = packets::ListDevices { request_id: 1 }.into();
+        let mut buf = BytesMut::new();
+        buf.put_slice(MAGIC);
+        buf.put_i32(1000);
+        buf.put_u64(3);
+        buf.put_slice(b"new");
+        buf.extend_from_slice(&encode(
+            &mut PartyPanelCodec::new(),
+            std::slice::from_ref(&packet),
+        ));
+
+        assert_eq!(decode_all(&mut PartyPanelCodec::new(), &mut buf), [packet]);
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let packets = every_packet();
+        let frames = encode(&mut PartyPanelCodec::new(), &packets[..2]);
+
+        let mut buf = BytesMut::new();
+        buf.put_slice(b"garbage, moo and mo");
+        buf.extend_from_slice(&frames);
+
+        let mut decoder = PartyPanelCodec::new().with_encoding(Encoding::Protobuf);
+        assert_eq!(decode_all(&mut decoder, &mut buf), packets[..2]);
+
+        // split inside the magic, one byte at a time
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in b"xxmo".iter().chain(frames.iter()) {
+            buf.put_u8(*byte);
+            decoded.extend(decoder.decode(&mut buf).unwrap());
+        }
+        assert_eq!(decoded, packets[..2]);
+    }
+
+    #[test]
+    fn gives_up_resyncing() {
+        let mut buf = BytesMut::from(&[b'x'; MAX_RESYNC_BYTES + MAGIC.len()][..]);
+        let mut decoder = PartyPanelCodec::new().with_encoding(Encoding::Protobuf);
+
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(ProtocolError::BadMagic { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        // only the header has arrived, the body is never waited for
+        let mut buf = BytesMut::new();
+        buf.put_slice(MAGIC);
+        buf.put_i32(PacketType::SongList as i32);
+        buf.put_u64(u64::MAX);
+
+        assert!(matches!(
+            PartyPanelCodec::new().decode(&mut buf),
+            Err(ProtocolError::FrameTooLarge { len: u64::MAX, .. })
+        ));
+
+        let mut buf = encode(&mut PartyPanelCodec::new(), &[big_song_list()]);
+        assert!(matches!(
+            PartyPanelCodec::with_max_frame_size(1024).decode(&mut buf),
+            Err(ProtocolError::FrameTooLarge { max: 1024, .. })
+        ));
+
+        // a JSON line that never ends
+        let mut buf = BytesMut::from(&b"{\"type\": \"SongList\", \"body\": \""[..]);
+        buf.resize(2048, b'a');
+        assert!(matches!(
+            PartyPanelCodec::with_max_frame_size(1024).decode(&mut buf),
+            Err(ProtocolError::FrameTooLarge { max: 1024, .. })
+        ));
+    }
+
+    #[test]
+    fn round_trips_compressed_frames() {
+        let packets = [
+            big_song_list(),
+            packets::StartPairing { request_id: 1 }.into(),
+        ];
+
+        for framing in [Framing::Legacy, Framing::Envelope] {
+            let mut encoder = PartyPanelCodec::new().with_framing(framing);
+            encoder.set_compression(true);
+            let mut buf = encode(&mut encoder, &packets);
+
+            let packet_type = (&buf[MAGIC.len()..]).get_i32();
+            assert_ne!(packet_type & COMPRESSED_FLAG, 0);
+            let len = (&buf[MAGIC.len() + size_of::<i32>()..]).get_u64() as usize;
+            assert!(len < packets[0].encoded_len() / 2);
+
+            // small ones are left alone
+            let second = &buf[HEADER_LEN + len..];
+            assert_eq!((&second[MAGIC.len()..]).get_i32() & COMPRESSED_FLAG, 0);
+
+            assert_eq!(decode_all(&mut PartyPanelCodec::new(), &mut buf), packets);
+        }
+    }
+
+    #[test]
+    fn bounds_inflated_frames() {
+        let mut encoder = PartyPanelCodec::new();
+        encoder.set_compression(true);
+        let mut buf = encode(&mut encoder, &[big_song_list()]);
+
+        // the compressed frame fits, what it inflates to doesn't
+        let compressed_len = buf.len() - HEADER_LEN;
+        let max = compressed_len * 2;
+        assert!(big_song_list().encoded_len() > max);
+
+        assert!(matches!(
+            PartyPanelCodec::with_max_frame_size(max).decode(&mut buf),
+            Err(ProtocolError::FrameTooLarge { max: m, .. }) if m == max
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::Ipv4Addr;
+
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::{debug, warn};
+
+use crate::{
+    codec::{Encoding, Framing, PartyPanelCodec},
+    error::ProtocolError,
+    proto::{packets::DiscoveryAnnouncement, Packet},
+};
+
+/// Where headsets listen for `DiscoveryQuery` broadcasts unless configured otherwise
+pub const DEFAULT_DISCOVERY_PORT: u16 = 48080;
+
+/// Multicast group headsets also join, for networks that drop broadcasts
+pub const DISCOVERY_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 80, 80);
+
+/// Queries and announcements are single "moon" frames, far smaller than this
+pub const MAX_DATAGRAM_SIZE: usize = 2048;
+
+/// Always protobuf with the v1 framing, so any version can read it
+fn codec() -> PartyPanelCodec {
+    PartyPanelCodec::with_max_frame_size(MAX_DATAGRAM_SIZE)
+        .with_encoding(Encoding::Protobuf)
+        .with_framing(Framing::Legacy)
+}
+
+pub fn encode_datagram(packet: impl Into<Packet>) -> Result<BytesMut, ProtocolError> {
+    let mut buf = BytesMut::new();
+    codec().encode(packet.into(), &mut buf)?;
+    Ok(buf)
+}
+
+/// `None` for anything that isn't a single packet we understand
+pub fn decode_datagram(datagram: &[u8]) -> Option<Packet> {
+    codec().decode(&mut BytesMut::from(datagram)).ok().flatten()
+}
+
+/// Binds `port` on every interface and joins [`DISCOVERY_MULTICAST_ADDR`],
+/// ready to [`respond`] on
+pub async fn bind(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).await?;
+    if let Err(e) = socket.join_multicast_v4(DISCOVERY_MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED) {
+        // broadcasts still work
+        warn!("Failed to join discovery multicast group: {}", e);
+    }
+
+    Ok(socket)
+}
+
+/// Answers every `DiscoveryQuery` that arrives on `socket` with whatever
+/// `announcement` returns at the time, sent straight back to whoever asked
+pub async fn respond(
+    socket: &UdpSocket,
+    announcement: impl Fn() -> DiscoveryAnnouncement,
+) -> Result<(), ProtocolError> {
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+
+        match decode_datagram(&buf[..len]) {
+            Some(Packet::DiscoveryQuery(_)) => {
+                debug!("Discovery query from {}", peer);
+                let reply = encode_datagram(announcement())?;
+                // one unreachable panel shouldn't stop everyone else finding the headset
+                if let Err(e) = socket.send_to(&reply, peer).await {
+                    warn!("Failed to answer discovery query from {}: {}", peer, e);
+                }
+            }
+            _ => debug!("Ignoring {} byte datagram from {}", len, peer),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::proto::{
+    packets::{ack::ErrorCode, Ack},
+    PacketType,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// No "moon" header could be found within the resynchronisation window
+    #[error("Invalid header, skipped {skipped} bytes without finding \"moon\"")]
+    BadMagic { skipped: usize },
+
+    #[error("Frame of {len} bytes exceeds the maximum frame size of {max} bytes")]
+    FrameTooLarge { len: u64, max: usize },
+
+    #[error("Unknown packet type {0}")]
+    UnknownPacketType(i32),
+
+    #[error("Unknown command type {0}")]
+    UnknownCommandType(i32),
+
+    #[error("Failed to decode {packet_type:?}: {source}")]
+    Decode {
+        packet_type: PacketType,
+        #[source]
+        source: prost::DecodeError,
+    },
+
+    /// `packet_type` is as framed, so an envelope or a [`PacketType`]
+    #[error("Failed to decompress packet type {packet_type}: {source}")]
+    Decompress {
+        packet_type: i32,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to decode packet envelope: {0}")]
+    DecodeEnvelope(#[source] prost::DecodeError),
+
+    /// An envelope without a payload we know about
+    #[error("Packet envelope has no payload")]
+    EmptyEnvelope,
+
+    #[error("Failed to encode packet: {0}")]
+    Encode(#[from] prost::EncodeError),
+
+    #[error("Failed to encode packet as JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// Nothing, not even a heartbeat, arrived within the heartbeat timeout
+    #[error("Nothing received from the peer for {0:?}")]
+    PeerTimeout(std::time::Duration),
+}
+
+/// Why a v1 message can't be converted to the typed v2 schema, or back.
+///
+/// v1 carries difficulties, characteristics and durations as display strings,
+/// so a typo in a difficulty is caught here instead of quietly playing the
+/// default one.
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    #[error("Unknown difficulty {0:?}")]
+    UnknownDifficulty(String),
+
+    #[error("No difficulty given")]
+    UnspecifiedDifficulty,
+
+    #[error("Unknown characteristic kind {0}")]
+    UnknownCharacteristic(i32),
+
+    #[error("No characteristic given")]
+    UnspecifiedCharacteristic,
+
+    #[error("Custom characteristic has no name")]
+    UnnamedCharacteristic,
+
+    #[error("Invalid duration {0:?}, expected m:ss")]
+    InvalidDuration(String),
+
+    #[error("Missing {0}")]
+    Missing(&'static str),
+}
+
+/// Why a panel's packet could not be carried out, reported back in an `Ack`
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+
+    #[error("{0:?} is not supported")]
+    Unsupported(PacketType),
+
+    #[error("Level {0} not found")]
+    LevelNotFound(String),
+
+    #[error("Characteristic {0} not found")]
+    CharacteristicNotFound(String),
+
+    #[error("Not in the main menu")]
+    NotInMenu,
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Device {0} not found")]
+    DeviceNotFound(String),
+
+    #[error("Invalid packet: {0}")]
+    Validation(#[from] ValidationError),
+
+    /// The headset failed to carry it out, for reasons that aren't the panel's
+    #[error("{0}")]
+    Game(String),
+}
+
+impl CommandError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommandError::InvalidPacket(_) => ErrorCode::InvalidPacket,
+            CommandError::Unsupported(_) => ErrorCode::Unsupported,
+            CommandError::LevelNotFound(_) => ErrorCode::LevelNotFound,
+            CommandError::CharacteristicNotFound(_) => ErrorCode::CharacteristicNotFound,
+            CommandError::NotInMenu => ErrorCode::NotInMenu,
+            CommandError::Unauthorized(_) => ErrorCode::Unauthorized,
+            CommandError::DeviceNotFound(_) => ErrorCode::DeviceNotFound,
+            CommandError::Validation(_) => ErrorCode::InvalidPacket,
+            CommandError::Game(_) => ErrorCode::GameError,
+        }
+    }
+}
+
+/// Answers request `request_id` with whether it was carried out
+pub fn ack(request_id: u32, result: Result<(), &CommandError>) -> Ack {
+    match result {
+        Ok(()) => Ack {
+            request_id,
+            success: true,
+            ..Default::default()
+        },
+        Err(e) => Ack {
+            request_id,
+            success: false,
+            error_code: e.code() as i32,
+            message: e.to_string(),
+        },
+    }
+}
@@ -0,0 +1,305 @@
+use std::{future::Future, time::Duration};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tracing::{info, warn};
+
+use crate::{
+    auth,
+    capabilities::{
+        COMPRESSION_CAPABILITY, HEARTBEAT_CAPABILITY, JSON_CAPABILITY, SONG_LIST_DELTAS_CAPABILITY,
+        SONG_LIST_PAGES_CAPABILITY,
+    },
+    error::{ack, CommandError, ProtocolError},
+    proto::{
+        packets::{Authenticate, Hello, Paired, Welcome},
+        Packet, PacketType, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    },
+};
+
+/// How long a panel has to send its first packet, and later to authenticate.
+/// Legacy panels never speak first, so a quiet one is assumed to be legacy.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wrong responses allowed before the panel is disconnected
+pub const MAX_AUTH_ATTEMPTS: u32 = 3;
+
+/// Packets a headset understands or sends, advertised in [`Welcome`]
+pub const SUPPORTED_PACKETS: &[PacketType] = &[
+    PacketType::SongList,
+    PacketType::Command,
+    PacketType::NowPlaying,
+    PacketType::NowPlayingUpdate,
+    PacketType::PlaySong,
+    PacketType::Hello,
+    PacketType::Welcome,
+    PacketType::Ack,
+    PacketType::ErrorEvent,
+    PacketType::Authenticate,
+    PacketType::StartPairing,
+    PacketType::PairingPin,
+    PacketType::Pair,
+    PacketType::Paired,
+    PacketType::ListDevices,
+    PacketType::DeviceList,
+    PacketType::RevokeDevice,
+    PacketType::SongListBegin,
+    PacketType::SongListPage,
+    PacketType::SongListEnd,
+    PacketType::SongsAdded,
+    PacketType::SongsRemoved,
+    PacketType::SongsChanged,
+    PacketType::SyncSongs,
+    PacketType::PlaySongV2,
+];
+
+/// Optional features on top of the packet set, advertised in [`Welcome`]
+pub const CAPABILITIES: &[&str] = &[
+    JSON_CAPABILITY,
+    COMPRESSION_CAPABILITY,
+    SONG_LIST_PAGES_CAPABILITY,
+    SONG_LIST_DELTAS_CAPABILITY,
+    HEARTBEAT_CAPABILITY,
+];
+
+/// What a headset accepts panels with, before any auth challenge
+pub fn welcome(mod_version: String, game_version: String, tls_fingerprint: String) -> Welcome {
+    Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        mod_version,
+        game_version,
+        supported_packets: SUPPORTED_PACKETS.iter().map(|p| *p as i32).collect(),
+        capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        accepted: true,
+        reason: String::new(),
+        auth_challenge: Vec::new(),
+        tls_fingerprint,
+    }
+}
+
+pub enum Handshake {
+    /// The panel introduced itself and was accepted
+    Accepted {
+        hello: Hello,
+        /// The paired device it authenticated as, if it didn't use the shared token
+        device_id: Option<String>,
+    },
+    /// The panel predates the handshake and went straight to sending packets,
+    /// or is waiting for the song list. The packet it sent, if any, still has
+    /// to be handled.
+    Legacy(Option<Packet>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+
+    #[error("Panel disconnected during the handshake")]
+    Disconnected,
+
+    #[error("Panel did not send Hello and can't authenticate")]
+    NoHello,
+
+    /// The panel was told why in a [`Welcome`]
+    #[error("Refused panel {client_name}: {reason}")]
+    Refused { client_name: String, reason: String },
+
+    #[error("Panel {0} did not authenticate in time")]
+    AuthTimeout(String),
+
+    #[error("Panel failed to authenticate {MAX_AUTH_ATTEMPTS} times")]
+    TooManyAttempts,
+}
+
+/// Who the headset lets in
+pub trait Gatekeeper: Sync {
+    /// Whether panels have to authenticate at all
+    fn auth_required(&self) -> bool;
+
+    /// What a panel authenticating as `device_id` signs the challenge with,
+    /// the shared token if `device_id` is empty
+    fn secret(&self, device_id: &str) -> Option<String>;
+
+    /// Pairs a new device with the PIN on offer
+    fn pair(
+        &self,
+        request_id: u32,
+        pin: &str,
+        device_name: String,
+    ) -> impl Future<Output = Result<Paired, CommandError>> + Send;
+}
+
+/// Why the headset won't talk to this panel, if anything
+fn check_compatible(hello: &Hello) -> Option<String> {
+    if hello.protocol_version < MIN_PROTOCOL_VERSION {
+        return Some(format!(
+            "Panel protocol version {} is too old, the mod requires at least {}",
+            hello.protocol_version, MIN_PROTOCOL_VERSION
+        ));
+    }
+
+    None
+}
+
+/// `None` if the panel sent nothing within [`HANDSHAKE_TIMEOUT`]
+async fn read_first<S>(socket: &mut S) -> Result<Option<Packet>, HandshakeError>
+where
+    S: Stream<Item = Result<Packet, ProtocolError>> + Unpin,
+{
+    let Ok(first) = tokio::time::timeout(HANDSHAKE_TIMEOUT, socket.next()).await else {
+        return Ok(None);
+    };
+    let first = first.ok_or(HandshakeError::Disconnected)??;
+
+    Ok(Some(first))
+}
+
+/// Waits for the panel's [`Hello`] and answers with `welcome`, the headset's
+/// [`welcome()`]. Refused panels are told why before the error is returned.
+///
+/// If the `gatekeeper` requires it, the panel also has to answer the
+/// challenge in the [`Welcome`] (or pair with a PIN) before this returns, so
+/// nothing it sends reaches the game until it has proven who it is.
+pub async fn accept<S, G>(
+    socket: &mut S,
+    welcome: Welcome,
+    gatekeeper: &G,
+) -> Result<Handshake, HandshakeError>
+where
+    S: Stream<Item = Result<Packet, ProtocolError>> + Sink<Packet, Error = ProtocolError> + Unpin,
+    G: Gatekeeper,
+{
+    let auth_required = gatekeeper.auth_required();
+
+    let hello = match read_first(socket).await? {
+        Some(Packet::Hello(hello)) => hello,
+        _ if auth_required => return Err(HandshakeError::NoHello),
+        Some(packet) => {
+            warn!("Panel did not send Hello, assuming a legacy panel");
+            return Ok(Handshake::Legacy(Some(packet)));
+        }
+        None => {
+            info!("Panel sent nothing within {HANDSHAKE_TIMEOUT:?}, assuming a legacy panel");
+            return Ok(Handshake::Legacy(None));
+        }
+    };
+
+    if let Some(reason) = check_compatible(&hello) {
+        let refusal = Welcome {
+            accepted: false,
+            reason: reason.clone(),
+            ..welcome
+        };
+        socket.send(refusal.into()).await?;
+        return Err(HandshakeError::Refused {
+            client_name: hello.client_name,
+            reason,
+        });
+    }
+
+    let challenge = if auth_required {
+        auth::challenge()
+    } else {
+        Vec::new()
+    };
+    let welcome = Welcome {
+        auth_challenge: challenge.clone(),
+        ..welcome
+    };
+    socket.send(welcome.into()).await?;
+
+    let device_id = if auth_required {
+        tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            authenticate(socket, gatekeeper, &challenge),
+        )
+        .await
+        .map_err(|_| HandshakeError::AuthTimeout(hello.client_name.clone()))??
+    } else {
+        None
+    };
+
+    info!(
+        "Panel {} {} connected with protocol version {}",
+        hello.client_name, hello.client_version, hello.protocol_version
+    );
+
+    Ok(Handshake::Accepted { hello, device_id })
+}
+
+/// Checks an [`Authenticate`] against the shared token or the device it names
+fn verify(gatekeeper: &impl Gatekeeper, challenge: &[u8], packet: &Authenticate) -> bool {
+    gatekeeper
+        .secret(&packet.device_id)
+        .is_some_and(|secret| auth::verify(&secret, challenge, &packet.response))
+}
+
+/// Reads packets until the panel sends a valid [`Authenticate`] or pairs with
+/// a PIN. Anything else is rejected, and too many wrong answers drop the panel.
+/// Returns the device the panel is, `None` if it used the shared token.
+async fn authenticate<S, G>(
+    socket: &mut S,
+    gatekeeper: &G,
+    challenge: &[u8],
+) -> Result<Option<String>, HandshakeError>
+where
+    S: Stream<Item = Result<Packet, ProtocolError>> + Sink<Packet, Error = ProtocolError> + Unpin,
+    G: Gatekeeper,
+{
+    let mut failures = 0;
+
+    while let Some(packet) = socket.next().await {
+        let packet = packet?;
+        let request_id = packet.request_id();
+
+        let result = match packet {
+            Packet::Authenticate(authenticate) => {
+                if verify(gatekeeper, challenge, &authenticate) {
+                    let device_id = Some(authenticate.device_id).filter(|id| !id.is_empty());
+                    Ok((device_id, None))
+                } else {
+                    Err(CommandError::Unauthorized("Wrong credentials".to_string()))
+                }
+            }
+            Packet::Pair(pair) => gatekeeper
+                .pair(request_id, &pair.pin, pair.device_name)
+                .await
+                .map(|paired| (Some(paired.device_id.clone()), Some(paired.into()))),
+            packet => {
+                warn!(
+                    "Ignoring {:?} from unauthenticated panel",
+                    packet.get_type()
+                );
+                if request_id != 0 {
+                    let error = CommandError::Unauthorized("Authenticate first".to_string());
+                    socket.send(ack(request_id, Err(&error)).into()).await?;
+                }
+                continue;
+            }
+        };
+
+        match result {
+            Ok((device_id, reply)) => {
+                let reply = match reply {
+                    Some(reply) => Some(reply),
+                    None if request_id != 0 => Some(ack(request_id, Ok(())).into()),
+                    None => None,
+                };
+                if let Some(reply) = reply {
+                    socket.send(reply).await?;
+                }
+                return Ok(device_id);
+            }
+            Err(e) => {
+                failures += 1;
+                socket.send(ack(request_id, Err(&e)).into()).await?;
+
+                if failures >= MAX_AUTH_ATTEMPTS {
+                    return Err(HandshakeError::TooManyAttempts);
+                }
+            }
+        }
+    }
+
+    Err(HandshakeError::Disconnected)
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::proto::{packets::Command, CommandType};
+
+pub fn is_heartbeat(command: &Command) -> bool {
+    command.command_type == CommandType::Heartbeat as i32
+}
+
+/// One connection's heartbeats: what was last sent, how long the echo took
+/// and when the peer was last heard from
+pub struct Heartbeat {
+    interval: Duration,
+    timeout: Option<Duration>,
+    next_id: u64,
+    /// Only the latest heartbeat is timed, a late echo of an older one is ignored
+    pending: Option<(u64, Instant)>,
+    round_trip: Option<Duration>,
+    last_received: Instant,
+}
+
+impl Heartbeat {
+    /// Without a `timeout` the peer is never considered gone
+    pub fn new(interval: Duration, timeout: Option<Duration>) -> Self {
+        Self {
+            interval,
+            timeout,
+            next_id: 1,
+            pending: None,
+            round_trip: None,
+            last_received: Instant::now(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The latest measured round trip, if any echo has come back yet
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.round_trip
+    }
+
+    fn round_trip_ms(&self) -> u32 {
+        self.round_trip
+            .map_or(0, |round_trip| round_trip.as_millis() as u32)
+    }
+
+    /// Call for every packet the peer sends, heartbeat or not
+    pub fn received(&mut self) {
+        self.last_received = Instant::now();
+    }
+
+    /// When the peer counts as gone if nothing else arrives
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| self.last_received + timeout)
+    }
+
+    /// The next heartbeat to send
+    pub fn ping(&mut self) -> Command {
+        let heartbeat_id = self.next_id;
+        self.next_id += 1;
+        self.pending = Some((heartbeat_id, Instant::now()));
+
+        Command {
+            command_type: CommandType::Heartbeat as i32,
+            heartbeat_id,
+            round_trip_ms: self.round_trip_ms(),
+            ..Default::default()
+        }
+    }
+
+    /// Times an echo of our own heartbeat, or returns the echo to send for
+    /// the peer's
+    pub fn handle(&mut self, command: &Command) -> Option<Command> {
+        if !command.heartbeat_reply {
+            return Some(Command {
+                command_type: CommandType::Heartbeat as i32,
+                request_id: command.request_id,
+                heartbeat_id: command.heartbeat_id,
+                heartbeat_reply: true,
+                round_trip_ms: self.round_trip_ms(),
+            });
+        }
+
+        match self.pending {
+            Some((heartbeat_id, sent)) if heartbeat_id == command.heartbeat_id => {
+                let round_trip = sent.elapsed();
+                debug!("Heartbeat round trip {:?}", round_trip);
+                self.round_trip = Some(round_trip);
+                self.pending = None;
+            }
+            _ => debug!("Ignoring stale heartbeat echo {}", command.heartbeat_id),
+        }
+
+        None
+    }
+}
@@ -0,0 +1,19 @@
+/// Challenge/response authentication with a shared token or device secret
+pub mod auth;
+/// Names advertised in `Hello.capabilities` and `Welcome.capabilities`.
+/// A feature is only used once both sides have advertised it.
+pub mod capabilities;
+pub mod codec;
+/// LAN discovery datagrams
+pub mod discovery;
+pub mod error;
+/// The headset's side of the Hello/Welcome handshake, shared by the mod and ppsim
+pub mod handshake;
+pub mod heartbeat;
+/// Pairing PINs and the credentials paired devices get
+pub mod pairing;
+pub mod proto;
+/// Conversions between the v1 messages and the typed v2 schema
+pub mod schema;
+/// Packets over plain byte streams or WebSockets
+pub mod transport;
@@ -0,0 +1,89 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::{distributions::Alphanumeric, Rng};
+use tracing::{info, warn};
+
+use crate::{auth, error::CommandError};
+
+/// How long a pairing PIN stays valid
+pub const PIN_TTL: Duration = Duration::from_secs(120);
+
+/// Wrong PINs accepted across all connections before the PIN is thrown away
+pub const MAX_PIN_ATTEMPTS: u32 = 5;
+
+const SECRET_LEN: usize = 32;
+const DEVICE_ID_LEN: usize = 12;
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// A new device id
+pub fn device_id() -> String {
+    random_string(DEVICE_ID_LEN)
+}
+
+/// A new device secret, what the device signs challenges with from then on
+pub fn secret() -> String {
+    random_string(SECRET_LEN)
+}
+
+struct Pin {
+    pin: String,
+    expires: Instant,
+    attempts: u32,
+}
+
+/// The PIN currently on offer, if any. Shared by every connection, so
+/// guessing across several of them doesn't get a panel more attempts.
+#[derive(Default)]
+pub struct PinSlot(Mutex<Option<Pin>>);
+
+impl PinSlot {
+    /// Makes a new PIN, replacing any previous one
+    pub fn start(&self) -> String {
+        let pin = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        *self.0.lock().unwrap() = Some(Pin {
+            pin: pin.clone(),
+            expires: Instant::now() + PIN_TTL,
+            attempts: 0,
+        });
+
+        info!("New pairing PIN, valid for {:?}", PIN_TTL);
+        pin
+    }
+
+    /// Uses up the PIN if `pin` is it. A wrong one only counts against
+    /// [`MAX_PIN_ATTEMPTS`], after which the PIN is gone either way.
+    pub fn redeem(&self, pin: &str) -> Result<(), CommandError> {
+        let mut current = self.0.lock().unwrap();
+        let valid = match current.as_mut() {
+            Some(current) if current.expires < Instant::now() => false,
+            Some(current) if auth::secrets_match(&current.pin, pin) => true,
+            Some(current) => {
+                current.attempts += 1;
+                if current.attempts < MAX_PIN_ATTEMPTS {
+                    return Err(CommandError::Unauthorized("Wrong PIN".to_string()));
+                }
+                warn!("Too many wrong pairing PINs, a new one has to be made");
+                false
+            }
+            None => false,
+        };
+
+        // single use either way
+        current.take();
+        if !valid {
+            return Err(CommandError::Unauthorized("No valid PIN".to_string()));
+        }
+
+        Ok(())
+    }
+}
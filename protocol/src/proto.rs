@@ -0,0 +1,620 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::ProtocolError;
+
+pub mod items {
+    include!(concat!(env!("OUT_DIR"), "/partypanel.items.rs"));
+}
+
+pub mod packets {
+    include!(concat!(env!("OUT_DIR"), "/partypanel.packets.rs"));
+}
+
+/// Typed counterparts of the v1 messages, see [`crate::schema`]
+pub mod v2 {
+    include!(concat!(env!("OUT_DIR"), "/partypanel.v2.rs"));
+}
+// include!(concat!(env!("OUT_DIR"), "/partypanel.items.rs"));
+// include!(concat!(env!("OUT_DIR"), "/partypanel.packets.rs"));
+
+/// Bumped whenever the wire format changes in a way panels need to know about.
+///
+/// 2 wraps every packet in a [`packets::Packet`] envelope, see
+/// [`crate::codec::Framing`].
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest panel protocol version the mod still talks to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PacketType {
+    SongList = 0,
+    Command = 1,
+    NowPlaying = 2,
+    NowPlayingUpdate = 3,
+    PlaySong = 4,
+    PreviewSong = 5,
+    DownloadSong = 6,
+    AllSongs = 7,
+    Hello = 8,
+    Welcome = 9,
+    Ack = 10,
+    ErrorEvent = 11,
+    Authenticate = 12,
+    StartPairing = 13,
+    PairingPin = 14,
+    Pair = 15,
+    Paired = 16,
+    ListDevices = 17,
+    DeviceList = 18,
+    RevokeDevice = 19,
+    SongListBegin = 20,
+    SongListPage = 21,
+    SongListEnd = 22,
+    SongsAdded = 23,
+    SongsRemoved = 24,
+    SongsChanged = 25,
+    SyncSongs = 26,
+    DiscoveryQuery = 27,
+    DiscoveryAnnouncement = 28,
+    /// [`v2::PlaySong`], for mods that advertise it
+    PlaySongV2 = 29,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommandType {
+    Unspecified = 0,
+    Heartbeat = 1,
+    ReturnToMenu = 2,
+}
+
+impl TryFrom<i32> for PacketType {
+    type Error = ProtocolError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PacketType::SongList),
+            1 => Ok(PacketType::Command),
+            2 => Ok(PacketType::NowPlaying),
+            3 => Ok(PacketType::NowPlayingUpdate),
+            4 => Ok(PacketType::PlaySong),
+            5 => Ok(PacketType::PreviewSong),
+            6 => Ok(PacketType::DownloadSong),
+            7 => Ok(PacketType::AllSongs),
+            8 => Ok(PacketType::Hello),
+            9 => Ok(PacketType::Welcome),
+            10 => Ok(PacketType::Ack),
+            11 => Ok(PacketType::ErrorEvent),
+            12 => Ok(PacketType::Authenticate),
+            13 => Ok(PacketType::StartPairing),
+            14 => Ok(PacketType::PairingPin),
+            15 => Ok(PacketType::Pair),
+            16 => Ok(PacketType::Paired),
+            17 => Ok(PacketType::ListDevices),
+            18 => Ok(PacketType::DeviceList),
+            19 => Ok(PacketType::RevokeDevice),
+            20 => Ok(PacketType::SongListBegin),
+            21 => Ok(PacketType::SongListPage),
+            22 => Ok(PacketType::SongListEnd),
+            23 => Ok(PacketType::SongsAdded),
+            24 => Ok(PacketType::SongsRemoved),
+            25 => Ok(PacketType::SongsChanged),
+            26 => Ok(PacketType::SyncSongs),
+            27 => Ok(PacketType::DiscoveryQuery),
+            28 => Ok(PacketType::DiscoveryAnnouncement),
+            29 => Ok(PacketType::PlaySongV2),
+            _ => Err(ProtocolError::UnknownPacketType(value)),
+        }
+    }
+}
+
+impl TryFrom<i32> for CommandType {
+    type Error = ProtocolError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CommandType::Unspecified),
+            1 => Ok(CommandType::Heartbeat),
+            2 => Ok(CommandType::ReturnToMenu),
+            _ => Err(ProtocolError::UnknownCommandType(value)),
+        }
+    }
+}
+
+pub trait PartyPacket: prost::Message + Into<Packet> {
+    fn get_type(&self) -> PacketType;
+}
+
+/// A packet decoded into its concrete protobuf message.
+///
+/// In JSON this is `{"type": "PlaySong", "body": {...}}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "body")]
+pub enum Packet {
+    SongList(packets::SongList),
+    Command(packets::Command),
+    NowPlaying(packets::NowPlaying),
+    NowPlayingUpdate(packets::NowPlayingUpdate),
+    PlaySong(packets::PlaySong),
+    PreviewSong(packets::PreviewSong),
+    DownloadSong(packets::DownloadSong),
+    AllSongs(packets::AllSongs),
+    Hello(packets::Hello),
+    Welcome(packets::Welcome),
+    Ack(packets::Ack),
+    ErrorEvent(packets::ErrorEvent),
+    Authenticate(packets::Authenticate),
+    StartPairing(packets::StartPairing),
+    PairingPin(packets::PairingPin),
+    Pair(packets::Pair),
+    Paired(packets::Paired),
+    ListDevices(packets::ListDevices),
+    DeviceList(packets::DeviceList),
+    RevokeDevice(packets::RevokeDevice),
+    SongListBegin(packets::SongListBegin),
+    SongListPage(packets::SongListPage),
+    SongListEnd(packets::SongListEnd),
+    SongsAdded(packets::SongsAdded),
+    SongsRemoved(packets::SongsRemoved),
+    SongsChanged(packets::SongsChanged),
+    SyncSongs(packets::SyncSongs),
+    DiscoveryQuery(packets::DiscoveryQuery),
+    DiscoveryAnnouncement(packets::DiscoveryAnnouncement),
+    PlaySongV2(v2::PlaySong),
+}
+
+impl Packet {
+    pub fn decode(
+        packet_type: PacketType,
+        body: impl bytes::Buf,
+    ) -> Result<Self, prost::DecodeError> {
+        use prost::Message;
+
+        let packet = match packet_type {
+            PacketType::SongList => Packet::SongList(Message::decode(body)?),
+            PacketType::Command => Packet::Command(Message::decode(body)?),
+            PacketType::NowPlaying => Packet::NowPlaying(Message::decode(body)?),
+            PacketType::NowPlayingUpdate => Packet::NowPlayingUpdate(Message::decode(body)?),
+            PacketType::PlaySong => Packet::PlaySong(Message::decode(body)?),
+            PacketType::PreviewSong => Packet::PreviewSong(Message::decode(body)?),
+            PacketType::DownloadSong => Packet::DownloadSong(Message::decode(body)?),
+            PacketType::AllSongs => Packet::AllSongs(Message::decode(body)?),
+            PacketType::Hello => Packet::Hello(Message::decode(body)?),
+            PacketType::Welcome => Packet::Welcome(Message::decode(body)?),
+            PacketType::Ack => Packet::Ack(Message::decode(body)?),
+            PacketType::ErrorEvent => Packet::ErrorEvent(Message::decode(body)?),
+            PacketType::Authenticate => Packet::Authenticate(Message::decode(body)?),
+            PacketType::StartPairing => Packet::StartPairing(Message::decode(body)?),
+            PacketType::PairingPin => Packet::PairingPin(Message::decode(body)?),
+            PacketType::Pair => Packet::Pair(Message::decode(body)?),
+            PacketType::Paired => Packet::Paired(Message::decode(body)?),
+            PacketType::ListDevices => Packet::ListDevices(Message::decode(body)?),
+            PacketType::DeviceList => Packet::DeviceList(Message::decode(body)?),
+            PacketType::RevokeDevice => Packet::RevokeDevice(Message::decode(body)?),
+            PacketType::SongListBegin => Packet::SongListBegin(Message::decode(body)?),
+            PacketType::SongListPage => Packet::SongListPage(Message::decode(body)?),
+            PacketType::SongListEnd => Packet::SongListEnd(Message::decode(body)?),
+            PacketType::SongsAdded => Packet::SongsAdded(Message::decode(body)?),
+            PacketType::SongsRemoved => Packet::SongsRemoved(Message::decode(body)?),
+            PacketType::SongsChanged => Packet::SongsChanged(Message::decode(body)?),
+            PacketType::SyncSongs => Packet::SyncSongs(Message::decode(body)?),
+            PacketType::DiscoveryQuery => Packet::DiscoveryQuery(Message::decode(body)?),
+            PacketType::DiscoveryAnnouncement => {
+                Packet::DiscoveryAnnouncement(Message::decode(body)?)
+            }
+            PacketType::PlaySongV2 => Packet::PlaySongV2(Message::decode(body)?),
+        };
+
+        Ok(packet)
+    }
+
+    pub fn get_type(&self) -> PacketType {
+        match self {
+            Packet::SongList(p) => p.get_type(),
+            Packet::Command(p) => p.get_type(),
+            Packet::NowPlaying(p) => p.get_type(),
+            Packet::NowPlayingUpdate(p) => p.get_type(),
+            Packet::PlaySong(p) => p.get_type(),
+            Packet::PreviewSong(p) => p.get_type(),
+            Packet::DownloadSong(p) => p.get_type(),
+            Packet::AllSongs(p) => p.get_type(),
+            Packet::Hello(p) => p.get_type(),
+            Packet::Welcome(p) => p.get_type(),
+            Packet::Ack(p) => p.get_type(),
+            Packet::ErrorEvent(p) => p.get_type(),
+            Packet::Authenticate(p) => p.get_type(),
+            Packet::StartPairing(p) => p.get_type(),
+            Packet::PairingPin(p) => p.get_type(),
+            Packet::Pair(p) => p.get_type(),
+            Packet::Paired(p) => p.get_type(),
+            Packet::ListDevices(p) => p.get_type(),
+            Packet::DeviceList(p) => p.get_type(),
+            Packet::RevokeDevice(p) => p.get_type(),
+            Packet::SongListBegin(p) => p.get_type(),
+            Packet::SongListPage(p) => p.get_type(),
+            Packet::SongListEnd(p) => p.get_type(),
+            Packet::SongsAdded(p) => p.get_type(),
+            Packet::SongsRemoved(p) => p.get_type(),
+            Packet::SongsChanged(p) => p.get_type(),
+            Packet::SyncSongs(p) => p.get_type(),
+            Packet::DiscoveryQuery(p) => p.get_type(),
+            Packet::DiscoveryAnnouncement(p) => p.get_type(),
+            Packet::PlaySongV2(p) => p.get_type(),
+        }
+    }
+
+    /// The id a panel wants echoed back in an [`packets::Ack`], 0 if none
+    pub fn request_id(&self) -> u32 {
+        match self {
+            Packet::PlaySong(p) => p.request_id,
+            Packet::Command(p) => p.request_id,
+            Packet::DownloadSong(p) => p.request_id,
+            Packet::Authenticate(p) => p.request_id,
+            Packet::StartPairing(p) => p.request_id,
+            Packet::Pair(p) => p.request_id,
+            Packet::ListDevices(p) => p.request_id,
+            Packet::RevokeDevice(p) => p.request_id,
+            Packet::SyncSongs(p) => p.request_id,
+            Packet::PlaySongV2(p) => p.request_id,
+            _ => 0,
+        }
+    }
+
+    /// Sets the id echoed back in an [`packets::Ack`], for packets that have one
+    pub fn set_request_id(&mut self, request_id: u32) {
+        match self {
+            Packet::PlaySong(p) => p.request_id = request_id,
+            Packet::Command(p) => p.request_id = request_id,
+            Packet::DownloadSong(p) => p.request_id = request_id,
+            Packet::Authenticate(p) => p.request_id = request_id,
+            Packet::StartPairing(p) => p.request_id = request_id,
+            Packet::Pair(p) => p.request_id = request_id,
+            Packet::ListDevices(p) => p.request_id = request_id,
+            Packet::RevokeDevice(p) => p.request_id = request_id,
+            Packet::SyncSongs(p) => p.request_id = request_id,
+            Packet::PlaySongV2(p) => p.request_id = request_id,
+            _ => {}
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        use prost::Message;
+
+        match self {
+            Packet::SongList(p) => p.encoded_len(),
+            Packet::Command(p) => p.encoded_len(),
+            Packet::NowPlaying(p) => p.encoded_len(),
+            Packet::NowPlayingUpdate(p) => p.encoded_len(),
+            Packet::PlaySong(p) => p.encoded_len(),
+            Packet::PreviewSong(p) => p.encoded_len(),
+            Packet::DownloadSong(p) => p.encoded_len(),
+            Packet::AllSongs(p) => p.encoded_len(),
+            Packet::Hello(p) => p.encoded_len(),
+            Packet::Welcome(p) => p.encoded_len(),
+            Packet::Ack(p) => p.encoded_len(),
+            Packet::ErrorEvent(p) => p.encoded_len(),
+            Packet::Authenticate(p) => p.encoded_len(),
+            Packet::StartPairing(p) => p.encoded_len(),
+            Packet::PairingPin(p) => p.encoded_len(),
+            Packet::Pair(p) => p.encoded_len(),
+            Packet::Paired(p) => p.encoded_len(),
+            Packet::ListDevices(p) => p.encoded_len(),
+            Packet::DeviceList(p) => p.encoded_len(),
+            Packet::RevokeDevice(p) => p.encoded_len(),
+            Packet::SongListBegin(p) => p.encoded_len(),
+            Packet::SongListPage(p) => p.encoded_len(),
+            Packet::SongListEnd(p) => p.encoded_len(),
+            Packet::SongsAdded(p) => p.encoded_len(),
+            Packet::SongsRemoved(p) => p.encoded_len(),
+            Packet::SongsChanged(p) => p.encoded_len(),
+            Packet::SyncSongs(p) => p.encoded_len(),
+            Packet::DiscoveryQuery(p) => p.encoded_len(),
+            Packet::DiscoveryAnnouncement(p) => p.encoded_len(),
+            Packet::PlaySongV2(p) => p.encoded_len(),
+        }
+    }
+
+    pub fn encode_body(&self, buf: &mut impl bytes::BufMut) -> Result<(), prost::EncodeError> {
+        use prost::Message;
+
+        match self {
+            Packet::SongList(p) => p.encode(buf),
+            Packet::Command(p) => p.encode(buf),
+            Packet::NowPlaying(p) => p.encode(buf),
+            Packet::NowPlayingUpdate(p) => p.encode(buf),
+            Packet::PlaySong(p) => p.encode(buf),
+            Packet::PreviewSong(p) => p.encode(buf),
+            Packet::DownloadSong(p) => p.encode(buf),
+            Packet::AllSongs(p) => p.encode(buf),
+            Packet::Hello(p) => p.encode(buf),
+            Packet::Welcome(p) => p.encode(buf),
+            Packet::Ack(p) => p.encode(buf),
+            Packet::ErrorEvent(p) => p.encode(buf),
+            Packet::Authenticate(p) => p.encode(buf),
+            Packet::StartPairing(p) => p.encode(buf),
+            Packet::PairingPin(p) => p.encode(buf),
+            Packet::Pair(p) => p.encode(buf),
+            Packet::Paired(p) => p.encode(buf),
+            Packet::ListDevices(p) => p.encode(buf),
+            Packet::DeviceList(p) => p.encode(buf),
+            Packet::RevokeDevice(p) => p.encode(buf),
+            Packet::SongListBegin(p) => p.encode(buf),
+            Packet::SongListPage(p) => p.encode(buf),
+            Packet::SongListEnd(p) => p.encode(buf),
+            Packet::SongsAdded(p) => p.encode(buf),
+            Packet::SongsRemoved(p) => p.encode(buf),
+            Packet::SongsChanged(p) => p.encode(buf),
+            Packet::SyncSongs(p) => p.encode(buf),
+            Packet::DiscoveryQuery(p) => p.encode(buf),
+            Packet::DiscoveryAnnouncement(p) => p.encode(buf),
+            Packet::PlaySongV2(p) => p.encode(buf),
+        }
+    }
+}
+
+macro_rules! impl_into_packet {
+    ($($name:ident),* $(,)?) => {
+        $(
+            impl From<packets::$name> for Packet {
+                fn from(value: packets::$name) -> Self {
+                    Packet::$name(value)
+                }
+            }
+        )*
+    };
+}
+
+impl_into_packet!(
+    SongList,
+    Command,
+    NowPlaying,
+    NowPlayingUpdate,
+    PlaySong,
+    PreviewSong,
+    DownloadSong,
+    AllSongs,
+    Hello,
+    Welcome,
+    Ack,
+    ErrorEvent,
+    Authenticate,
+    StartPairing,
+    PairingPin,
+    Pair,
+    Paired,
+    ListDevices,
+    DeviceList,
+    RevokeDevice,
+    SongListBegin,
+    SongListPage,
+    SongListEnd,
+    SongsAdded,
+    SongsRemoved,
+    SongsChanged,
+    SyncSongs,
+    DiscoveryQuery,
+    DiscoveryAnnouncement,
+);
+
+impl From<v2::PlaySong> for Packet {
+    fn from(value: v2::PlaySong) -> Self {
+        Packet::PlaySongV2(value)
+    }
+}
+
+macro_rules! impl_envelope {
+    ($($name:ident),* $(,)?) => {
+        impl From<Packet> for packets::Packet {
+            fn from(value: Packet) -> Self {
+                let request_id = value.request_id();
+                let payload = match value {
+                    $(Packet::$name(p) => packets::packet::Payload::$name(p),)*
+                };
+
+                Self {
+                    request_id,
+                    payload: Some(payload),
+                    ..Default::default()
+                }
+            }
+        }
+
+        impl TryFrom<packets::Packet> for Packet {
+            type Error = ProtocolError;
+
+            fn try_from(value: packets::Packet) -> Result<Self, Self::Error> {
+                // unset if the payload is newer than us
+                let mut packet = match value.payload.ok_or(ProtocolError::EmptyEnvelope)? {
+                    $(packets::packet::Payload::$name(p) => Packet::$name(p),)*
+                };
+                if value.request_id != 0 {
+                    packet.set_request_id(value.request_id);
+                }
+
+                Ok(packet)
+            }
+        }
+    };
+}
+
+impl_envelope!(
+    SongList,
+    Command,
+    NowPlaying,
+    NowPlayingUpdate,
+    PlaySong,
+    PreviewSong,
+    DownloadSong,
+    AllSongs,
+    Hello,
+    Welcome,
+    Ack,
+    ErrorEvent,
+    Authenticate,
+    StartPairing,
+    PairingPin,
+    Pair,
+    Paired,
+    ListDevices,
+    DeviceList,
+    RevokeDevice,
+    SongListBegin,
+    SongListPage,
+    SongListEnd,
+    SongsAdded,
+    SongsRemoved,
+    SongsChanged,
+    SyncSongs,
+    DiscoveryQuery,
+    DiscoveryAnnouncement,
+    PlaySongV2,
+);
+
+impl PartyPacket for packets::SongList {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongList
+    }
+}
+impl PartyPacket for packets::Command {
+    fn get_type(&self) -> PacketType {
+        PacketType::Command
+    }
+}
+impl PartyPacket for packets::NowPlaying {
+    fn get_type(&self) -> PacketType {
+        PacketType::NowPlaying
+    }
+}
+impl PartyPacket for packets::NowPlayingUpdate {
+    fn get_type(&self) -> PacketType {
+        PacketType::NowPlayingUpdate
+    }
+}
+impl PartyPacket for packets::PlaySong {
+    fn get_type(&self) -> PacketType {
+        PacketType::PlaySong
+    }
+}
+impl PartyPacket for packets::PreviewSong {
+    fn get_type(&self) -> PacketType {
+        PacketType::PreviewSong
+    }
+}
+impl PartyPacket for packets::DownloadSong {
+    fn get_type(&self) -> PacketType {
+        PacketType::DownloadSong
+    }
+}
+impl PartyPacket for packets::AllSongs {
+    fn get_type(&self) -> PacketType {
+        PacketType::AllSongs
+    }
+}
+impl PartyPacket for packets::Hello {
+    fn get_type(&self) -> PacketType {
+        PacketType::Hello
+    }
+}
+impl PartyPacket for packets::Welcome {
+    fn get_type(&self) -> PacketType {
+        PacketType::Welcome
+    }
+}
+impl PartyPacket for packets::Ack {
+    fn get_type(&self) -> PacketType {
+        PacketType::Ack
+    }
+}
+impl PartyPacket for packets::ErrorEvent {
+    fn get_type(&self) -> PacketType {
+        PacketType::ErrorEvent
+    }
+}
+impl PartyPacket for packets::Authenticate {
+    fn get_type(&self) -> PacketType {
+        PacketType::Authenticate
+    }
+}
+impl PartyPacket for packets::StartPairing {
+    fn get_type(&self) -> PacketType {
+        PacketType::StartPairing
+    }
+}
+impl PartyPacket for packets::PairingPin {
+    fn get_type(&self) -> PacketType {
+        PacketType::PairingPin
+    }
+}
+impl PartyPacket for packets::Pair {
+    fn get_type(&self) -> PacketType {
+        PacketType::Pair
+    }
+}
+impl PartyPacket for packets::Paired {
+    fn get_type(&self) -> PacketType {
+        PacketType::Paired
+    }
+}
+impl PartyPacket for packets::ListDevices {
+    fn get_type(&self) -> PacketType {
+        PacketType::ListDevices
+    }
+}
+impl PartyPacket for packets::DeviceList {
+    fn get_type(&self) -> PacketType {
+        PacketType::DeviceList
+    }
+}
+impl PartyPacket for packets::RevokeDevice {
+    fn get_type(&self) -> PacketType {
+        PacketType::RevokeDevice
+    }
+}
+impl PartyPacket for packets::SongListBegin {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongListBegin
+    }
+}
+impl PartyPacket for packets::SongListPage {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongListPage
+    }
+}
+impl PartyPacket for packets::SongListEnd {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongListEnd
+    }
+}
+impl PartyPacket for packets::SongsAdded {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongsAdded
+    }
+}
+impl PartyPacket for packets::SongsRemoved {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongsRemoved
+    }
+}
+impl PartyPacket for packets::SongsChanged {
+    fn get_type(&self) -> PacketType {
+        PacketType::SongsChanged
+    }
+}
+impl PartyPacket for packets::SyncSongs {
+    fn get_type(&self) -> PacketType {
+        PacketType::SyncSongs
+    }
+}
+impl PartyPacket for packets::DiscoveryQuery {
+    fn get_type(&self) -> PacketType {
+        PacketType::DiscoveryQuery
+    }
+}
+impl PartyPacket for packets::DiscoveryAnnouncement {
+    fn get_type(&self) -> PacketType {
+        PacketType::DiscoveryAnnouncement
+    }
+}
+impl PartyPacket for v2::PlaySong {
+    fn get_type(&self) -> PacketType {
+        PacketType::PlaySongV2
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    error::ValidationError,
+    proto::{
+        items, packets,
+        v2::{self, CharacteristicKind, Difficulty},
+    },
+};
+
+const DIFFICULTY_NAMES: &[(Difficulty, &str)] = &[
+    (Difficulty::Easy, "Easy"),
+    (Difficulty::Normal, "Normal"),
+    (Difficulty::Hard, "Hard"),
+    (Difficulty::Expert, "Expert"),
+    (Difficulty::ExpertPlus, "ExpertPlus"),
+];
+
+const CHARACTERISTIC_NAMES: &[(CharacteristicKind, &str)] = &[
+    (CharacteristicKind::Standard, "Standard"),
+    (CharacteristicKind::OneSaber, "OneSaber"),
+    (CharacteristicKind::NoArrows, "NoArrows"),
+    (CharacteristicKind::Degree360, "360Degree"),
+    (CharacteristicKind::Degree90, "90Degree"),
+    (CharacteristicKind::Lightshow, "Lightshow"),
+    (CharacteristicKind::Lawless, "Lawless"),
+    (CharacteristicKind::Legacy, "Legacy"),
+];
+
+impl Difficulty {
+    /// The name v1 messages use, `None` for [`Difficulty::Unspecified`]
+    pub fn name(self) -> Option<&'static str> {
+        DIFFICULTY_NAMES
+            .iter()
+            .find(|(difficulty, _)| *difficulty == self)
+            .map(|(_, name)| *name)
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, ValidationError> {
+        DIFFICULTY_NAMES
+            .iter()
+            .find(|(_, known)| *known == name)
+            .map(|(difficulty, _)| *difficulty)
+            .ok_or_else(|| ValidationError::UnknownDifficulty(name.to_string()))
+    }
+
+    /// Checks a v2 message's raw enum value
+    pub fn validate(value: i32) -> Result<Self, ValidationError> {
+        match Self::try_from(value) {
+            Ok(Self::Unspecified) => Err(ValidationError::UnspecifiedDifficulty),
+            Ok(difficulty) => Ok(difficulty),
+            Err(_) => Err(ValidationError::UnknownDifficulty(value.to_string())),
+        }
+    }
+}
+
+impl CharacteristicKind {
+    /// The name the game serializes the characteristic as, `None` for the
+    /// unspecified and custom kinds
+    pub fn serialized_name(self) -> Option<&'static str> {
+        CHARACTERISTIC_NAMES
+            .iter()
+            .find(|(kind, _)| *kind == self)
+            .map(|(_, name)| *name)
+    }
+
+    /// Anything the base game doesn't have is [`CharacteristicKind::Custom`]
+    pub fn from_serialized_name(name: &str) -> Self {
+        CHARACTERISTIC_NAMES
+            .iter()
+            .find(|(_, known)| *known == name)
+            .map_or(Self::Custom, |(kind, _)| *kind)
+    }
+}
+
+impl v2::Characteristic {
+    /// What v1 messages and the game call this characteristic
+    pub fn serialized_name(&self) -> Result<&str, ValidationError> {
+        match CharacteristicKind::try_from(self.kind) {
+            Ok(CharacteristicKind::Custom) if self.custom_name.is_empty() => {
+                Err(ValidationError::UnnamedCharacteristic)
+            }
+            Ok(CharacteristicKind::Custom) => Ok(&self.custom_name),
+            Ok(kind) => kind
+                .serialized_name()
+                .ok_or(ValidationError::UnspecifiedCharacteristic),
+            Err(_) => Err(ValidationError::UnknownCharacteristic(self.kind)),
+        }
+    }
+
+    pub fn from_serialized_name(name: &str) -> Self {
+        let kind = CharacteristicKind::from_serialized_name(name);
+        let custom_name = match kind {
+            CharacteristicKind::Custom => name.to_string(),
+            _ => String::new(),
+        };
+
+        Self {
+            kind: kind as i32,
+            custom_name,
+            difficulties: Vec::new(),
+        }
+    }
+}
+
+/// `m:ss`, empty if the duration isn't known or isn't one [`parse_duration`]
+/// could read back. Zero is still `0:00`, as v1 panels have always been sent.
+pub fn format_duration(secs: f32) -> String {
+    if !secs.is_finite() || secs < 0.0 {
+        return String::new();
+    }
+    let minutes = (secs / 60.0) as i32;
+    let seconds = (secs % 60.0) as i32;
+    format!("{}:{:02}", minutes, seconds)
+}
+
+/// Reverses [`format_duration`], 0 for an empty duration
+pub fn parse_duration(duration: &str) -> Result<f32, ValidationError> {
+    if duration.is_empty() {
+        return Ok(0.0);
+    }
+
+    let invalid = || ValidationError::InvalidDuration(duration.to_string());
+    let (minutes, seconds) = duration.split_once(':').ok_or_else(invalid)?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    let seconds: u32 = seconds.parse().map_err(|_| invalid())?;
+    if seconds >= 60 {
+        return Err(invalid());
+    }
+
+    let secs = minutes
+        .checked_mul(60)
+        .and_then(|minutes| minutes.checked_add(seconds))
+        .ok_or_else(invalid)?;
+    Ok(secs as f32)
+}
+
+impl TryFrom<items::Characteristic> for v2::Characteristic {
+    type Error = ValidationError;
+
+    fn try_from(value: items::Characteristic) -> Result<Self, Self::Error> {
+        let difficulties = value
+            .diffs
+            .iter()
+            .map(|name| {
+                Ok(v2::DifficultyLevel {
+                    difficulty: Difficulty::from_name(name)? as i32,
+                    custom_label: String::new(),
+                })
+            })
+            .collect::<Result<_, ValidationError>>()?;
+
+        Ok(Self {
+            difficulties,
+            ..Self::from_serialized_name(&value.name)
+        })
+    }
+}
+
+impl TryFrom<v2::Characteristic> for items::Characteristic {
+    type Error = ValidationError;
+
+    fn try_from(value: v2::Characteristic) -> Result<Self, Self::Error> {
+        let diffs = value
+            .difficulties
+            .iter()
+            .map(|level| {
+                let difficulty = Difficulty::validate(level.difficulty)?;
+                Ok(difficulty.name().unwrap_or_default().to_string())
+            })
+            .collect::<Result<_, ValidationError>>()?;
+
+        Ok(Self {
+            name: value.serialized_name()?.to_string(),
+            diffs,
+        })
+    }
+}
+
+impl TryFrom<items::PreviewBeatmapLevel> for v2::PreviewBeatmapLevel {
+    type Error = ValidationError;
+
+    fn try_from(value: items::PreviewBeatmapLevel) -> Result<Self, Self::Error> {
+        Ok(Self {
+            duration_secs: parse_duration(&value.duration)?,
+            characteristics: value
+                .chars
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            level_id: value.level_id,
+            name: value.name,
+            sub_name: value.sub_name,
+            author: value.author,
+            mapper: value.mapper,
+            bpm: value.bpm,
+            cover: value.cover,
+            cover_path: value.cover_path,
+            favorited: value.favorited,
+            owned: value.owned,
+            owned_justification: value.owned_justification,
+        })
+    }
+}
+
+impl TryFrom<v2::PreviewBeatmapLevel> for items::PreviewBeatmapLevel {
+    type Error = ValidationError;
+
+    fn try_from(value: v2::PreviewBeatmapLevel) -> Result<Self, Self::Error> {
+        Ok(Self {
+            duration: format_duration(value.duration_secs),
+            chars: value
+                .characteristics
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            level_id: value.level_id,
+            name: value.name,
+            sub_name: value.sub_name,
+            author: value.author,
+            mapper: value.mapper,
+            bpm: value.bpm,
+            cover: value.cover,
+            cover_path: value.cover_path,
+            favorited: value.favorited,
+            owned: value.owned,
+            owned_justification: value.owned_justification,
+        })
+    }
+}
+
+impl TryFrom<packets::PlaySong> for v2::PlaySong {
+    type Error = ValidationError;
+
+    fn try_from(value: packets::PlaySong) -> Result<Self, Self::Error> {
+        let characteristic = value
+            .characteristic
+            .ok_or(ValidationError::Missing("characteristic"))?;
+
+        Ok(Self {
+            level_id: value.level_id,
+            difficulty: Difficulty::from_name(&value.difficulty)? as i32,
+            characteristic: Some(v2::Characteristic::from_serialized_name(
+                &characteristic.name,
+            )),
+            gameplay_modifiers: value.gameplay_modifiers,
+            request_id: value.request_id,
+        })
+    }
+}
+
+impl TryFrom<v2::PlaySong> for packets::PlaySong {
+    type Error = ValidationError;
+
+    fn try_from(value: v2::PlaySong) -> Result<Self, Self::Error> {
+        let difficulty = Difficulty::validate(value.difficulty)?;
+        let characteristic = value
+            .characteristic
+            .ok_or(ValidationError::Missing("characteristic"))?;
+
+        Ok(Self {
+            level_id: value.level_id,
+            difficulty: difficulty.name().unwrap_or_default().to_string(),
+            characteristic: Some(items::Characteristic {
+                name: characteristic.serialized_name()?.to_string(),
+                diffs: Vec::new(),
+            }),
+            gameplay_modifiers: value.gameplay_modifiers,
+            request_id: value.request_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_round_trip() {
+        for (secs, formatted) in [
+            (0.0, "0:00"),
+            (59.9, "0:59"),
+            (185.0, "3:05"),
+            (6000.0, "100:00"),
+        ] {
+            assert_eq!(format_duration(secs), formatted);
+            assert_eq!(parse_duration(formatted).unwrap(), secs.floor());
+        }
+        for secs in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -5.0] {
+            assert_eq!(format_duration(secs), "", "{secs}");
+        }
+        assert_eq!(parse_duration("").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for duration in ["3", "3:60", "-1:00", "a:00", "71582789:00", "4294967295:59"] {
+            assert!(
+                matches!(
+                    parse_duration(duration),
+                    Err(ValidationError::InvalidDuration(_))
+                ),
+                "{duration}"
+            );
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, BytesMut};
+use futures::{stream::BoxStream, Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tokio_util::{
+    codec::{Encoder, FramedRead, FramedWrite},
+    io::StreamReader,
+};
+
+use crate::{
+    codec::{Encoding, PartyPanelCodec},
+    error::ProtocolError,
+    proto::Packet,
+};
+
+pub type PacketReader = BoxStream<'static, Result<Packet, ProtocolError>>;
+pub type PacketWriter = Pin<Box<dyn Sink<Packet, Error = ProtocolError> + Send>>;
+
+/// A connection to a panel, independent of what carries the packets
+pub struct Transport {
+    pub reader: PacketReader,
+    pub writer: PacketWriter,
+    /// Shares its negotiated settings with the codecs inside `reader` and `writer`
+    pub codec: PartyPanelCodec,
+}
+
+impl Transport {
+    /// Plain "moon" frames over any byte stream
+    pub fn from_io<T>(io: T, codec: PartyPanelCodec) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (read, write) = tokio::io::split(io);
+
+        Self {
+            reader: FramedRead::new(read, codec.clone()).boxed(),
+            writer: Box::pin(FramedWrite::new(write, codec.clone())),
+            codec,
+        }
+    }
+
+    /// Each binary message carries "moon" frames, so browsers get exactly
+    /// the same packets as native panels. Text messages carry JSON packets
+    /// and are answered in kind.
+    pub fn from_websocket<S>(ws: WebSocketStream<S>, codec: PartyPanelCodec) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (ws_writer, ws_reader) = ws.split();
+
+        let bytes = ws_reader.filter_map(|message| async move {
+            match message {
+                Ok(Message::Binary(data)) => Some(Ok(data)),
+                Ok(Message::Text(text)) => {
+                    // the codec splits JSON packets on newlines
+                    let mut data = BytesMut::from(text.as_bytes());
+                    data.put_u8(b'\n');
+                    Some(Ok(data.freeze()))
+                }
+                // pings are answered by tungstenite, closes end the stream
+                Ok(_) => None,
+                Err(e) => Some(Err(io::Error::other(e))),
+            }
+        });
+
+        let mut encoder = codec.clone();
+        let writer = ws_writer
+            .sink_map_err(|e| ProtocolError::Io(io::Error::other(e)))
+            .with(move |packet: Packet| {
+                let mut buf = BytesMut::new();
+                let message = encoder.encode(packet, &mut buf).and_then(|_| {
+                    if encoder.encoding() != Some(Encoding::Json) {
+                        return Ok(Message::Binary(buf.freeze()));
+                    }
+
+                    let text = buf.trim_ascii_end();
+                    let text = String::from_utf8(text.to_vec())
+                        .map_err(|e| ProtocolError::Io(io::Error::other(e)))?;
+                    Ok(Message::text(text))
+                });
+                futures::future::ready(message)
+            });
+
+        Self {
+            reader: FramedRead::new(StreamReader::new(bytes), codec.clone()).boxed(),
+            writer: Box::pin(writer),
+            codec,
+        }
+    }
+}
+
+impl Stream for Transport {
+    type Item = Result<Packet, ProtocolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.reader.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<Packet> for Transport {
+    type Error = ProtocolError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.writer.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Packet) -> Result<(), Self::Error> {
+        self.writer.as_mut().start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.writer.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.writer.as_mut().poll_close(cx)
+    }
+}
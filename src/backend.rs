@@ -0,0 +1,83 @@
+use std::future::Future;
+
+use crate::proto::{
+    items::{GameplayModifiers, PreviewBeatmapLevel},
+    packets::NowPlayingUpdate,
+    v2::Difficulty,
+};
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(target_os = "android")]
+mod il2cpp;
+
+#[cfg(target_os = "android")]
+pub use il2cpp::Il2CppBackend;
+
+/// How far into the level being played the player is
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScoreState {
+    pub score: i32,
+    pub accuracy: f64,
+    /// Seconds of the song played so far
+    pub elapsed: f32,
+    /// Length of the song in seconds
+    pub length: f32,
+}
+
+impl From<ScoreState> for NowPlayingUpdate {
+    fn from(score: ScoreState) -> Self {
+        NowPlayingUpdate {
+            score: score.score,
+            accuracy: score.accuracy,
+            elapsed: score.elapsed as i32,
+            total_time: score.length as i32,
+        }
+    }
+}
+
+/// Everything the mod needs from the game.
+///
+/// The [`WebContext`](crate::web_context::WebContext) only goes through this,
+/// so it can run against the in-memory fake as well as Beat Saber. Backends
+/// are cloned into background tasks, so a clone has to reach the same game.
+pub trait GameBackend: Clone + Send + 'static {
+    /// A level as the game hands it out
+    type Level: Clone + Send + 'static;
+
+    /// Every level the game has loaded
+    fn levels(&self) -> anyhow::Result<Vec<Self::Level>>;
+
+    fn level_id(&self, level: &Self::Level) -> String;
+
+    /// The level as panels see it, except for whether it is favorited or owned
+    fn describe(&self, level: &Self::Level) -> anyhow::Result<PreviewBeatmapLevel>;
+
+    fn is_favorite(&self, level_id: &str) -> anyhow::Result<bool>;
+
+    /// Whether the player has the level, which can mean asking the store
+    fn is_owned(&mut self, level_id: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
+
+    /// Gives up on every [`GameBackend::is_owned`] started before, through
+    /// any clone
+    fn cancel_lookups(&mut self) -> anyhow::Result<()>;
+
+    /// Fails with a [`CommandError`](crate::error::CommandError) if the
+    /// player isn't somewhere the level can be started from, or the level
+    /// doesn't have `characteristic`
+    fn start_level(
+        &mut self,
+        level: Self::Level,
+        characteristic: &str,
+        difficulty: Difficulty,
+        modifiers: &GameplayModifiers,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Quits the level being played, if any
+    fn return_to_menu(&self);
+
+    /// `None` if there is no level to read it from
+    // only polled by the game's hooks
+    #[cfg_attr(not(target_os = "android"), allow(dead_code))]
+    fn score(&self) -> anyhow::Result<Option<ScoreState>>;
+}
@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{GameBackend, ScoreState};
+use crate::{
+    error::CommandError,
+    proto::{
+        items::{GameplayModifiers, PreviewBeatmapLevel},
+        v2::Difficulty,
+    },
+    schema::parse_duration,
+};
+
+/// A level in the [`FakeBackend`]'s library
+#[derive(Clone, Debug, Default)]
+pub struct FakeLevel {
+    /// Favorited and owned are ignored, see the fields below
+    pub preview: PreviewBeatmapLevel,
+    pub favorite: bool,
+    pub owned: bool,
+}
+
+/// What the [`FakeBackend`] was last asked to play
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartedLevel {
+    pub level_id: String,
+    pub characteristic: String,
+    pub difficulty: Difficulty,
+    pub modifiers: GameplayModifiers,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeGame {
+    pub levels: Vec<FakeLevel>,
+    /// `None` while in the menu
+    pub playing: Option<StartedLevel>,
+    /// Reset whenever a level starts
+    pub score: Option<ScoreState>,
+}
+
+/// A game that only exists in memory. Levels never progress on their own,
+/// set [`FakeGame::score`] to move one along.
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend(Arc<Mutex<FakeGame>>);
+
+impl FakeBackend {
+    pub fn new(levels: Vec<FakeLevel>) -> Self {
+        Self(Arc::new(Mutex::new(FakeGame {
+            levels,
+            ..Default::default()
+        })))
+    }
+
+    /// The game every clone shares, for inspecting or changing it
+    pub fn game(&self) -> MutexGuard<'_, FakeGame> {
+        self.0.lock().unwrap()
+    }
+
+    fn level(&self, level_id: &str) -> Option<FakeLevel> {
+        self.game()
+            .levels
+            .iter()
+            .find(|level| level.preview.level_id == level_id)
+            .cloned()
+    }
+}
+
+impl GameBackend for FakeBackend {
+    type Level = String;
+
+    fn levels(&self) -> anyhow::Result<Vec<Self::Level>> {
+        Ok(self
+            .game()
+            .levels
+            .iter()
+            .map(|level| level.preview.level_id.clone())
+            .collect())
+    }
+
+    fn level_id(&self, level: &Self::Level) -> String {
+        level.clone()
+    }
+
+    fn describe(&self, level: &Self::Level) -> anyhow::Result<PreviewBeatmapLevel> {
+        let level = self
+            .level(level)
+            .ok_or_else(|| CommandError::LevelNotFound(level.clone()))?;
+
+        Ok(PreviewBeatmapLevel {
+            favorited: false,
+            owned: false,
+            owned_justification: String::new(),
+            ..level.preview
+        })
+    }
+
+    fn is_favorite(&self, level_id: &str) -> anyhow::Result<bool> {
+        Ok(self.level(level_id).is_some_and(|level| level.favorite))
+    }
+
+    async fn is_owned(&mut self, level_id: &str) -> anyhow::Result<bool> {
+        Ok(self.level(level_id).is_some_and(|level| level.owned))
+    }
+
+    fn cancel_lookups(&mut self) -> anyhow::Result<()> {
+        // lookups finish immediately
+        Ok(())
+    }
+
+    async fn start_level(
+        &mut self,
+        level: Self::Level,
+        characteristic: &str,
+        difficulty: Difficulty,
+        modifiers: &GameplayModifiers,
+    ) -> anyhow::Result<()> {
+        let found = self
+            .level(&level)
+            .ok_or_else(|| CommandError::LevelNotFound(level.clone()))?;
+        if !found.preview.chars.iter().any(|c| c.name == characteristic) {
+            return Err(CommandError::CharacteristicNotFound(characteristic.to_string()).into());
+        }
+        let length = parse_duration(&found.preview.duration)?;
+
+        let mut game = self.game();
+        if game.playing.is_some() {
+            return Err(CommandError::NotInMenu.into());
+        }
+
+        game.playing = Some(StartedLevel {
+            level_id: level,
+            characteristic: characteristic.to_string(),
+            difficulty,
+            modifiers: *modifiers,
+        });
+        game.score = Some(ScoreState {
+            length,
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    fn return_to_menu(&self) {
+        let mut game = self.game();
+        game.playing = None;
+        game.score = None;
+    }
+
+    fn score(&self) -> anyhow::Result<Option<ScoreState>> {
+        Ok(self.game().score)
+    }
+}
@@ -0,0 +1,427 @@
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use bs_cordl::{
+    GlobalNamespace::{
+        AdditionalContentModel, BeatmapCharacteristicSO, BeatmapDifficulty, BeatmapKey,
+        BeatmapLevel, BeatmapLevelsModel, EntitlementStatus, GameplayModifiers,
+        GameplayModifiers_EnabledObstacleType, GameplayModifiers_EnergyType,
+        GameplayModifiers_SongSpeed, MainFlowCoordinator, MenuTransitionsHelper, PlayerDataModel,
+        PracticeSettings, ScoreController, SoloFreePlayFlowCoordinator,
+        StandardLevelReturnToMenuController,
+    },
+    System::{
+        self, Nullable_1,
+        Threading::{CancellationToken, CancellationTokenSource},
+    },
+    UnityEngine::Resources,
+    HMUI::NoTransitionsButton,
+};
+use itertools::Itertools;
+use quest_hook::libil2cpp::{Gc, Il2CppString};
+use tracing::info;
+
+use super::{GameBackend, ScoreState};
+use crate::{
+    async_utils::Il2CPPFutureAwaitable,
+    error::CommandError,
+    game::party_panel_run_on_main_thread,
+    proto::{self, items::PreviewBeatmapLevel, v2::Difficulty},
+    schema,
+};
+
+/// Levels from SongCore's last load
+static LEVELS: Mutex<Vec<Gc<BeatmapLevel>>> = Mutex::new(Vec::new());
+
+/// Beat Saber itself
+#[derive(Clone)]
+pub struct Il2CppBackend {
+    player_data: Gc<PlayerDataModel>,
+    get_status_cancellation_token_source: Option<Gc<CancellationTokenSource>>,
+    flow: Option<Gc<SoloFreePlayFlowCoordinator>>,
+    /// Found by the level's `Init` hook, Unity only hands it out on the main thread
+    score_controller: Option<Gc<ScoreController>>,
+}
+
+impl Il2CppBackend {
+    pub fn new(player_data: Gc<PlayerDataModel>) -> Self {
+        Self {
+            player_data,
+            get_status_cancellation_token_source: None,
+            flow: None,
+            score_controller: None,
+        }
+    }
+
+    pub fn set_score_controller(&mut self, score_controller: Gc<ScoreController>) {
+        self.score_controller = Some(score_controller);
+    }
+
+    /// Called whenever SongCore finishes (re)loading levels
+    pub fn set_levels(levels: Vec<Gc<BeatmapLevel>>) {
+        *LEVELS.lock().unwrap() = levels;
+    }
+
+    pub fn convert_practice(
+        practice_settings: &PracticeSettings,
+    ) -> quest_hook::libil2cpp::Result<Gc<PracticeSettings>> {
+        PracticeSettings::New_f32_f32_2(0.0, practice_settings._songSpeedMul / 10000.0)
+    }
+
+    pub fn convert_modifiers(
+        mods: &proto::items::GameplayModifiers,
+    ) -> quest_hook::libil2cpp::Result<Gc<GameplayModifiers>> {
+        GameplayModifiers::New_GameplayModifiers_EnergyType__cordl_bool__cordl_bool__cordl_bool_GameplayModifiers_EnabledObstacleType__cordl_bool__cordl_bool__cordl_bool__cordl_bool_GameplayModifiers_SongSpeed__cordl_bool__cordl_bool__cordl_bool__cordl_bool__cordl_bool1(
+            energy_type_from_i32(mods.energy_type),
+            mods.no_fail_on_0_energy,
+            mods.insta_fail,
+            mods.fail_on_saber_clash,
+            obstacle_type_from_i32(mods.enabled_obstacle_type),
+            mods.no_bombs,
+            false,
+            mods.strict_angles,
+            mods.disappearing_arrows,
+            song_speed_from_i32(mods.song_speed),
+            mods.no_arrows,
+            mods.ghost_notes,
+            mods.pro_mode,
+            mods.zen_mode,
+            mods.small_cubes,
+        )
+    }
+
+    async fn play_song(
+        &mut self,
+        beatmap_level: Gc<BeatmapLevel>,
+        characteristic: Gc<BeatmapCharacteristicSO>,
+        difficulty: BeatmapDifficulty,
+        modifiers: &proto::items::GameplayModifiers,
+    ) -> anyhow::Result<()> {
+        self.flow = Resources::FindObjectsOfTypeAll_1::<Gc<MainFlowCoordinator>>()?
+            .as_slice()
+            .first()
+            .map(|flow| flow._soloFreePlayFlowCoordinator);
+
+        let Some(flow) = self.flow else {
+            return Err(CommandError::NotInMenu.into());
+        };
+
+        extern "C" fn click_solo_button(_: *mut std::ffi::c_void) {
+            let mut solo_button = Resources::FindObjectsOfTypeAll_1::<Gc<NoTransitionsButton>>()
+                .unwrap()
+                .as_slice()
+                .iter()
+                .cloned()
+                .find(|x| {
+                    !x.is_null()
+                        && x.clone()
+                            .get_gameObject()
+                            .unwrap()
+                            .get_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            == "SoloButton"
+                })
+                .expect("No solo button found");
+            solo_button.get_onClick().unwrap().Invoke().unwrap();
+        }
+
+        unsafe { party_panel_run_on_main_thread(click_solo_button, std::ptr::null_mut()) }
+
+        let mut menu_scene_setup_data =
+            Resources::FindObjectsOfTypeAll_1::<Gc<MenuTransitionsHelper>>()?
+                .as_slice()
+                .first()
+                .copied()
+                .ok_or(anyhow!("No MenuTransitionsHelper found"))?;
+
+        let key = BeatmapKey {
+            beatmapCharacteristic: characteristic,
+            difficulty,
+            levelId: beatmap_level.levelID,
+        };
+        let mut gameplay_setup_view_controller = flow._gameplaySetupViewController;
+        let environment_settings =
+            gameplay_setup_view_controller.get_environmentOverrideSettings()?;
+        let scheme = gameplay_setup_view_controller
+            .get_colorSchemesSettings()?
+            .GetSelectedColorScheme()?;
+        let settings = gameplay_setup_view_controller.get_playerSettings()?;
+
+        let modifiers = Self::convert_modifiers(modifiers)?;
+
+        menu_scene_setup_data.StartStandardLevel_OverrideEnvironmentSettings_ColorScheme__cordl_bool_ColorScheme_GameplayModifiers_PlayerSpecificSettings_PracticeSettings_EnvironmentsListModel_Il2CppString__cordl_bool_Action_Action_1_Action_2_Nullable_1_0(
+            Il2CppString::new("Solo"),
+            key,
+            beatmap_level,
+            environment_settings,
+            scheme,
+            false,
+            Gc::null(),
+            modifiers,
+            settings,
+            Gc::null(),
+            Gc::null(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Nullable_1::default() ,
+        )?;
+
+        Ok(())
+    }
+
+    async fn has_dlc_level(
+        level_id: &str,
+        additional_content_model: Option<Gc<AdditionalContentModel>>,
+        token: Option<CancellationToken>,
+    ) -> anyhow::Result<bool> {
+        if !level_id.starts_with("custom_level_") {
+            info!("{}", level_id);
+        }
+
+        let model = additional_content_model.or_else(|| {
+            Resources::FindObjectsOfTypeAll_1::<Gc<AdditionalContentModel>>()
+                .ok()
+                .and_then(|models| models.as_slice().first().cloned())
+        });
+
+        let Some(mut model) = model else {
+            return Ok(false);
+        };
+
+        let status = model
+            .IAdditionalContentEntitlementModel_GetLevelEntitlementStatusAsync(
+                Il2CppString::new(level_id),
+                token.unwrap_or_default(),
+            )?
+            .into_awaitable()
+            .await?;
+
+        Ok(status == EntitlementStatus::Owned)
+    }
+
+    pub fn get_level_from_preview(
+        &self,
+        level: &PreviewBeatmapLevel,
+    ) -> anyhow::Result<Option<Gc<BeatmapLevel>>> {
+        let beatmap_levels_model = Resources::FindObjectsOfTypeAll_1::<Gc<BeatmapLevelsModel>>()?
+            .as_slice()
+            .first()
+            .cloned();
+
+        let Some(mut beatmap_levels_model) = beatmap_levels_model else {
+            return Ok(None);
+        };
+
+        let result = beatmap_levels_model.GetBeatmapLevel(Il2CppString::new(&level.level_id));
+
+        match result {
+            Ok(level) => Ok(Some(level)),
+            _ => {
+                info!("Failed to load Level");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Favorited and owned are up to the caller, see
+    /// [`GameBackend::is_favorite`] and [`GameBackend::is_owned`]
+    fn convert_to_packet_type(mut x: Gc<BeatmapLevel>) -> anyhow::Result<PreviewBeatmapLevel> {
+        let mut level = PreviewBeatmapLevel {
+            level_id: x.levelID.to_string_lossy(),
+            name: x.songName.to_string_lossy().to_string(),
+            sub_name: x.songSubName.to_string_lossy().to_string(),
+            author: x.songAuthorName.to_string_lossy().to_string(),
+            mapper: x
+                .allMappers
+                .as_slice()
+                .iter()
+                .map(|m| m.to_string_lossy())
+                .join(","),
+            bpm: x.beatsPerMinute,
+            duration: schema::format_duration(x.songDuration),
+            ..Default::default()
+        };
+        level.chars = System::Linq::Enumerable::ToList(x.GetBeatmapKeys()?)?
+            ._items
+            .as_slice()
+            .iter()
+            .filter(|i| **i != BeatmapKey::default())
+            .map(|i| (i.beatmapCharacteristic, i.difficulty))
+            .chunk_by(|(characteristic, _)| *characteristic)
+            .into_iter()
+            .map(
+                |(characteristic, difficulties)| -> quest_hook::libil2cpp::Result<_> {
+                    let char = proto::items::Characteristic {
+                        name: characteristic
+                            .clone()
+                            .get_serializedName()?
+                            .to_string_lossy()
+                            .to_string(),
+                        diffs: difficulties
+                            .map(|(_, diff)| difficulty_name(diff))
+                            .collect::<Vec<_>>(),
+                    };
+                    Ok(char)
+                },
+            )
+            .try_collect()?;
+
+        // Skip cover image handling for now as it requires more complex Unity texture manipulation
+
+        Ok(level)
+    }
+}
+
+impl GameBackend for Il2CppBackend {
+    type Level = Gc<BeatmapLevel>;
+
+    fn levels(&self) -> anyhow::Result<Vec<Self::Level>> {
+        Ok(LEVELS.lock().unwrap().clone())
+    }
+
+    fn level_id(&self, level: &Self::Level) -> String {
+        // mappers love invalid UTF-8/UTF-16!
+        level.levelID.to_string_lossy()
+    }
+
+    fn describe(&self, level: &Self::Level) -> anyhow::Result<PreviewBeatmapLevel> {
+        Self::convert_to_packet_type(*level)
+    }
+
+    fn is_favorite(&self, level_id: &str) -> anyhow::Result<bool> {
+        let mut player_data = self.player_data._playerData;
+        Ok(player_data
+            .get_favoritesLevelIds()?
+            .Contains(Il2CppString::new(level_id))?)
+    }
+
+    async fn is_owned(&mut self, level_id: &str) -> anyhow::Result<bool> {
+        let token = self
+            .get_status_cancellation_token_source
+            .map(|mut source| source.get_Token())
+            .transpose()?;
+
+        Self::has_dlc_level(level_id, None, token).await
+    }
+
+    fn cancel_lookups(&mut self) -> anyhow::Result<()> {
+        if let Some(mut source) = self.get_status_cancellation_token_source {
+            source.Cancel_0()?;
+        }
+        self.get_status_cancellation_token_source = Some(CancellationTokenSource::New_0()?);
+
+        Ok(())
+    }
+
+    async fn start_level(
+        &mut self,
+        level: Self::Level,
+        characteristic_name: &str,
+        difficulty: Difficulty,
+        modifiers: &proto::items::GameplayModifiers,
+    ) -> anyhow::Result<()> {
+        let characteristic = self
+            .player_data
+            ._playerDataFileModel
+            ._beatmapCharacteristicCollection
+            .GetBeatmapCharacteristicBySerializedName(Il2CppString::new(characteristic_name))
+            .ok()
+            .filter(|characteristic| !characteristic.is_null())
+            .ok_or_else(|| CommandError::CharacteristicNotFound(characteristic_name.to_string()))?;
+
+        self.play_song(
+            level,
+            characteristic,
+            beatmap_difficulty(difficulty),
+            modifiers,
+        )
+        .await
+    }
+
+    fn return_to_menu(&self) {
+        extern "C" fn return_to_main_menu_callback(_: *mut std::ffi::c_void) {
+            let Ok(controllers) =
+                Resources::FindObjectsOfTypeAll_1::<Gc<StandardLevelReturnToMenuController>>()
+            else {
+                return;
+            };
+            let Some(mut controller) = controllers.as_slice().first().cloned() else {
+                return;
+            };
+            let _ = controller.ReturnToMenu();
+        }
+
+        unsafe {
+            party_panel_run_on_main_thread(return_to_main_menu_callback, std::ptr::null_mut());
+        }
+    }
+
+    fn score(&self) -> anyhow::Result<Option<ScoreState>> {
+        let Some(score) = self.score_controller else {
+            return Ok(None);
+        };
+
+        let mut audio = score._audioTimeSyncController;
+        Ok(Some(ScoreState {
+            score: score._modifiedScore,
+            accuracy: 0.0,
+            elapsed: audio._songTime,
+            length: audio.get_songLength()?,
+        }))
+    }
+}
+
+fn energy_type_from_i32(value: i32) -> GameplayModifiers_EnergyType {
+    match value {
+        0 => GameplayModifiers_EnergyType::Bar,
+        1 => GameplayModifiers_EnergyType::Battery,
+        _ => GameplayModifiers_EnergyType::Bar,
+    }
+}
+
+fn obstacle_type_from_i32(value: i32) -> GameplayModifiers_EnabledObstacleType {
+    match value {
+        0 => GameplayModifiers_EnabledObstacleType::All,
+        1 => GameplayModifiers_EnabledObstacleType::FullHeightOnly,
+        2 => GameplayModifiers_EnabledObstacleType::NoObstacles,
+        _ => GameplayModifiers_EnabledObstacleType::All,
+    }
+}
+
+fn song_speed_from_i32(value: i32) -> GameplayModifiers_SongSpeed {
+    match value {
+        0 => GameplayModifiers_SongSpeed::Normal,
+        1 => GameplayModifiers_SongSpeed::Faster,
+        2 => GameplayModifiers_SongSpeed::Slower,
+        3 => GameplayModifiers_SongSpeed::SuperFast,
+        _ => GameplayModifiers_SongSpeed::Normal,
+    }
+}
+
+fn difficulty_name(difficulty: BeatmapDifficulty) -> String {
+    match difficulty {
+        BeatmapDifficulty::Easy => "Easy".to_string(),
+        BeatmapDifficulty::Normal => "Normal".to_string(),
+        BeatmapDifficulty::Hard => "Hard".to_string(),
+        BeatmapDifficulty::Expert => "Expert".to_string(),
+        BeatmapDifficulty::ExpertPlus => "ExpertPlus".to_string(),
+        _ => "Unknown".to_string(),
+    }
+}
+
+fn beatmap_difficulty(difficulty: Difficulty) -> BeatmapDifficulty {
+    match difficulty {
+        Difficulty::Easy => BeatmapDifficulty::Easy,
+        Difficulty::Normal => BeatmapDifficulty::Normal,
+        Difficulty::Hard => BeatmapDifficulty::Hard,
+        Difficulty::Expert => BeatmapDifficulty::Expert,
+        Difficulty::ExpertPlus => BeatmapDifficulty::ExpertPlus,
+        Difficulty::Unspecified => BeatmapDifficulty::default(),
+    }
+}
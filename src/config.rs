@@ -1,6 +1,162 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
+use party_panel_protocol::discovery::DEFAULT_DISCOVERY_PORT;
+
+use crate::{codec::DEFAULT_MAX_FRAME_SIZE, transport::TransportKind};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionMode {
+    /// The mod dials out to the panel at `addr`
+    #[default]
+    Connect,
+    /// The mod binds `listen_addr` and panels dial in
+    Listen,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
+    pub mode: ConnectionMode,
     pub addr: String,
+    pub listen_addr: String,
+    pub transport: TransportKind,
+    /// Largest packet body accepted from a panel, in bytes
+    pub max_frame_size: usize,
+    /// Shared secret panels have to prove they know before sending commands.
+    /// Anyone who can reach the mod can control the game if this is unset.
+    pub auth_token: Option<String>,
+    /// Encrypt connections. In listen mode the mod serves its self-signed
+    /// certificate and panels pin its fingerprint, in connect mode it is the
+    /// TLS client and pins `panel_fingerprint`.
+    pub tls: bool,
+    /// SHA-256 of the panel's certificate, as colon separated hex. Required
+    /// for TLS in connect mode.
+    pub panel_fingerprint: Option<String>,
+    /// Shown to panels looking for headsets on the network
+    pub name: String,
+    /// Answer discovery queries from panels on the LAN
+    pub discovery: bool,
+    /// UDP port discovery queries arrive on
+    pub discovery_port: u16,
+    /// How often to send a heartbeat to panels that support them
+    pub heartbeat_interval_secs: u64,
+    /// How long such a panel can stay silent before it is disconnected.
+    /// 0 never disconnects.
+    pub heartbeat_timeout_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: ConnectionMode::Connect,
+            addr: "127.0.0.1:8080".to_string(),
+            listen_addr: "0.0.0.0:8080".to_string(),
+            transport: TransportKind::Tcp,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            auth_token: None,
+            tls: false,
+            panel_fingerprint: None,
+            name: "Beat Saber".to_string(),
+            discovery: true,
+            discovery_port: DEFAULT_DISCOVERY_PORT,
+            heartbeat_interval_secs: 5,
+            heartbeat_timeout_secs: 15,
+        }
+    }
+}
+
+/// `/sdcard/ModData/<app>/Configs`, where everything the mod persists lives
+#[cfg(target_os = "android")]
+pub fn config_dir() -> PathBuf {
+    let id = unsafe {
+        std::ffi::CStr::from_ptr(scotland2_rs::scotland2_raw::modloader_get_application_id())
+    };
+    format!("/sdcard/ModData/{}/Configs", id.to_string_lossy()).into()
+}
+
+/// Somewhere harmless for host builds
+#[cfg(not(target_os = "android"))]
+pub fn config_dir() -> PathBuf {
+    std::env::temp_dir().join("party_panel")
+}
+
+/// The game's own data directory, which other apps can't read, for secrets
+#[cfg(target_os = "android")]
+pub fn data_dir() -> PathBuf {
+    let id = unsafe {
+        std::ffi::CStr::from_ptr(scotland2_rs::scotland2_raw::modloader_get_application_id())
+    };
+    format!("/data/data/{}/files/party_panel", id.to_string_lossy()).into()
+}
+
+#[cfg(not(target_os = "android"))]
+pub fn data_dir() -> PathBuf {
+    config_dir().join("data")
+}
+
+/// Creates `path` readable by the game alone
+pub async fn write_private(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path).await?;
+    file.write_all(data).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+impl Config {
+    /// The token panels must authenticate with, if any
+    pub fn auth_token(&self) -> Option<&str> {
+        self.auth_token.as_deref().filter(|token| !token.is_empty())
+    }
+
+    /// The panel certificate fingerprint to pin, if any
+    pub fn panel_fingerprint(&self) -> Option<&str> {
+        self.panel_fingerprint
+            .as_deref()
+            .filter(|fingerprint| !fingerprint.is_empty())
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs.max(1))
+    }
+
+    pub fn heartbeat_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.heartbeat_timeout_secs)).filter(|timeout| !timeout.is_zero())
+    }
+
+    pub fn path() -> PathBuf {
+        config_dir().join("config.json")
+    }
+
+    /// Reads the config, writing the defaults out if there is none yet
+    pub async fn load() -> anyhow::Result<Self> {
+        let path = Self::path();
+
+        let config: Config = if tokio::fs::try_exists(&path).await? {
+            let data = tokio::fs::read(path)
+                .await
+                .context("Config unable to be loaded")?;
+
+            serde_json::from_slice(&data).context("Failed to parse config")?
+        } else {
+            let config = Config::default();
+            tokio::fs::write(path, serde_json::to_vec(&config)?).await?;
+            config
+        };
+
+        Ok(config)
+    }
 }
@@ -0,0 +1,223 @@
+use std::{
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use party_panel_protocol::pairing::{self, PinSlot, PIN_TTL};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config,
+    error::{ack, CommandError},
+    hub::{ClientId, HUB},
+    proto::{
+        packets::{device_list, DeviceList, Paired, PairingPin},
+        Packet,
+    },
+};
+
+pub static DEVICES: LazyLock<Devices> = LazyLock::new(Devices::default);
+
+/// A panel that was paired with a PIN and authenticates with its own secret
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    secret: String,
+    /// unix seconds
+    pub paired_at: u64,
+}
+
+/// Paired panels, persisted to `devices.json` in the game's private data
+/// since the secrets are credentials, and the PIN currently on offer
+#[derive(Default)]
+pub struct Devices {
+    devices: Mutex<Vec<Device>>,
+    pin: PinSlot,
+}
+
+impl Devices {
+    pub fn path() -> PathBuf {
+        config::data_dir().join("devices.json")
+    }
+
+    /// Reads the paired devices, if any were saved
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let path = Self::path();
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+
+        let data = tokio::fs::read(path)
+            .await
+            .context("Devices unable to be loaded")?;
+        let devices: Vec<Device> =
+            serde_json::from_slice(&data).context("Failed to parse devices")?;
+
+        info!("Loaded {} paired devices", devices.len());
+        *self.devices.lock().unwrap() = devices;
+
+        Ok(())
+    }
+
+    async fn save(&self) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(&*self.devices.lock().unwrap())?;
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        config::write_private(&path, &data)
+            .await
+            .context("Failed to save devices")?;
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.lock().unwrap().is_empty()
+    }
+
+    pub fn secret(&self, device_id: &str) -> Option<String> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|device| device.id == device_id)
+            .map(|device| device.secret.clone())
+    }
+
+    pub fn list(&self) -> Vec<Device> {
+        self.devices.lock().unwrap().clone()
+    }
+
+    /// Makes a new PIN, replacing any previous one
+    pub fn start_pairing(&self) -> String {
+        self.pin.start()
+    }
+
+    /// Uses up the PIN and issues a credential for a new device
+    pub async fn pair(&self, pin: &str, name: String) -> anyhow::Result<Device> {
+        self.pin.redeem(pin)?;
+
+        let paired_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let device = Device {
+            id: pairing::device_id(),
+            name,
+            secret: pairing::secret(),
+            paired_at,
+        };
+
+        self.devices.lock().unwrap().push(device.clone());
+        if let Err(e) = self.save().await {
+            // a credential that is gone after a restart is worse than none
+            self.devices.lock().unwrap().retain(|d| d.id != device.id);
+            return Err(e);
+        }
+        info!("Paired device {} ({})", device.name, device.id);
+
+        Ok(device)
+    }
+
+    /// Forgets a device and disconnects every panel authenticated as it
+    pub async fn revoke(&self, device_id: &str) -> anyhow::Result<()> {
+        let (index, device) = {
+            let mut devices = self.devices.lock().unwrap();
+            let Some(index) = devices.iter().position(|device| device.id == device_id) else {
+                return Err(CommandError::DeviceNotFound(device_id.to_string()).into());
+            };
+
+            (index, devices.remove(index))
+        };
+
+        if let Err(e) = self.save().await {
+            // still trusted after a restart, so keep trusting it now
+            let mut devices = self.devices.lock().unwrap();
+            let index = index.min(devices.len());
+            devices.insert(index, device);
+            return Err(e);
+        }
+        info!("Revoked device {} ({})", device.name, device.id);
+
+        HUB.disconnect_device(device_id);
+
+        Ok(())
+    }
+}
+
+/// The [`Paired`] answer for a successful pairing
+pub fn paired(request_id: u32, device: &Device) -> Paired {
+    Paired {
+        request_id,
+        device_id: device.id.clone(),
+        secret: device.secret.clone(),
+    }
+}
+
+pub fn is_device_packet(packet: &Packet) -> bool {
+    matches!(
+        packet,
+        Packet::StartPairing(_)
+            | Packet::Pair(_)
+            | Packet::ListDevices(_)
+            | Packet::RevokeDevice(_)
+    )
+}
+
+/// Answers a pairing or device management packet from an authenticated panel
+pub async fn handle(client_id: ClientId, packet: Packet) {
+    let request_id = packet.request_id();
+
+    let result = match packet {
+        Packet::StartPairing(_) => {
+            let pin = DEVICES.start_pairing();
+            HUB.send_to(
+                client_id,
+                PairingPin {
+                    request_id,
+                    pin,
+                    expires_in_secs: PIN_TTL.as_secs() as u32,
+                },
+            );
+            return;
+        }
+        Packet::Pair(pair) => match DEVICES.pair(&pair.pin, pair.device_name).await {
+            Ok(device) => {
+                HUB.send_to(client_id, paired(request_id, &device));
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        Packet::ListDevices(_) => {
+            let devices = DEVICES
+                .list()
+                .into_iter()
+                .map(|device| device_list::Device {
+                    device_id: device.id,
+                    name: device.name,
+                    paired_at: device.paired_at,
+                })
+                .collect();
+            HUB.send_to(
+                client_id,
+                DeviceList {
+                    request_id,
+                    devices,
+                },
+            );
+            return;
+        }
+        Packet::RevokeDevice(revoke) => DEVICES.revoke(&revoke.device_id).await,
+        packet => Err(CommandError::Unsupported(packet.get_type()).into()),
+    };
+
+    if let Err(e) = &result {
+        warn!("Device request failed: {:?}", e);
+    }
+    HUB.send_to(client_id, ack(request_id, &result));
+}
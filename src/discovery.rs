@@ -0,0 +1,50 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use party_panel_protocol::discovery::{bind, respond};
+use tracing::info;
+
+use crate::{
+    config::{Config, ConnectionMode},
+    devices::DEVICES,
+    proto::{packets::DiscoveryAnnouncement, PROTOCOL_VERSION},
+    tls,
+};
+
+/// What this headset tells panels about itself
+pub fn announcement(config: &Config) -> DiscoveryAnnouncement {
+    let listen_port = match config.mode {
+        ConnectionMode::Listen => config
+            .listen_addr
+            .parse::<SocketAddr>()
+            .map_or(0, |addr| addr.port() as u32),
+        ConnectionMode::Connect => 0,
+    };
+
+    DiscoveryAnnouncement {
+        name: config.name.clone(),
+        mod_version: crate::MOD_VERSION.to_string(),
+        game_version: crate::GAME_VERSION.get().cloned().unwrap_or_default(),
+        protocol_version: PROTOCOL_VERSION,
+        listening: config.mode == ConnectionMode::Listen,
+        listen_port,
+        auth_required: config.auth_token().is_some() || !DEVICES.is_empty(),
+        tls_fingerprint: tls::IDENTITY
+            .get()
+            .map(|identity| identity.fingerprint.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// Answers discovery queries on `config.discovery_port` with this
+/// headset's [`announcement`]
+pub async fn run(config: Arc<Config>) -> anyhow::Result<()> {
+    let socket = bind(config.discovery_port)
+        .await
+        .with_context(|| format!("Failed to bind discovery port {}", config.discovery_port))?;
+    info!("Answering discovery queries on {}", socket.local_addr()?);
+
+    respond(&socket, || announcement(&config)).await?;
+
+    Ok(())
+}
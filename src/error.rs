@@ -0,0 +1,24 @@
+pub use party_panel_protocol::error::{CommandError, ProtocolError};
+
+use crate::proto::packets::Ack;
+
+/// The [`CommandError`] to tell the panel about, anything that isn't one
+/// went wrong in the game
+pub fn command_error(e: anyhow::Error) -> CommandError {
+    e.downcast::<CommandError>()
+        .unwrap_or_else(|e| CommandError::Game(e.to_string()))
+}
+
+/// Turns the outcome of a panel packet into its [`Ack`]
+pub fn ack(request_id: u32, result: &anyhow::Result<()>) -> Ack {
+    match result {
+        Ok(()) => party_panel_protocol::error::ack(request_id, Ok(())),
+        Err(e) => match e.downcast_ref::<CommandError>() {
+            Some(error) => party_panel_protocol::error::ack(request_id, Err(error)),
+            None => {
+                let error = CommandError::Game(e.to_string());
+                party_panel_protocol::error::ack(request_id, Err(&error))
+            }
+        },
+    }
+}
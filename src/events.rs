@@ -0,0 +1,43 @@
+use std::fmt::Display;
+
+use tracing::{error, info, warn};
+
+use crate::{
+    hub::{ClientId, HUB},
+    proto::packets::{
+        error_event::{Severity, Source},
+        ErrorEvent,
+    },
+};
+
+fn error_event(
+    source: Source,
+    severity: Severity,
+    message: impl Display,
+    level_id: Option<&str>,
+) -> ErrorEvent {
+    let event = ErrorEvent {
+        source: source as i32,
+        severity: severity as i32,
+        message: message.to_string(),
+        level_id: level_id.unwrap_or_default().to_string(),
+    };
+
+    match severity {
+        Severity::Error => error!("{:?}: {}", source, event.message),
+        Severity::Warning => warn!("{:?}: {}", source, event.message),
+        Severity::Info | Severity::Unspecified => info!("{:?}: {}", source, event.message),
+    }
+
+    event
+}
+
+/// Logs a background failure and pushes it to every connected panel
+pub fn report(source: Source, severity: Severity, message: impl Display, level_id: Option<&str>) {
+    HUB.broadcast(error_event(source, severity, message, level_id));
+}
+
+/// Logs a failure only one panel cares about and pushes it to that panel
+pub fn report_to(client_id: ClientId, source: Source, severity: Severity, message: impl Display) {
+    HUB.send_to(client_id, error_event(source, severity, message, None));
+}
@@ -0,0 +1,258 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bs_cordl::GlobalNamespace::{
+    AudioClipAsyncLoader, BeatmapDataLoader, BeatmapKey, BeatmapLevel, BeatmapLevelPack,
+    BeatmapLevelsEntitlementModel, BeatmapLevelsModel, ColorScheme, EnvironmentsListModel,
+    GameplayModifiers, LevelCompletionResults, OverrideEnvironmentSettings, PlayerDataModel,
+    PlayerSpecificSettings, PracticeSettings, RecordingToolManager_SetupData, ScoreController,
+    SettingsManager, StandardLevelScenesTransitionSetupDataSO,
+};
+use bs_cordl::UnityEngine::{Application, Resources};
+use quest_hook::hook;
+use quest_hook::libil2cpp::{Gc, Il2CppString};
+use scotland2_rs::scotland2_raw::CModInfo;
+use scotland2_rs::ModInfoBuf;
+use tracing::debug;
+
+use crate::{
+    backend::{GameBackend, Il2CppBackend},
+    events,
+    hub::HUB,
+    proto::packets::{
+        error_event::{Severity, Source},
+        NowPlaying, NowPlayingUpdate,
+    },
+    web_context::{WebContext, WebContextMessage},
+    GAME_VERSION, MOD_ID, MOD_VERSION, NOW_PLAYING, RUNTIME, WEB_CONTEXT,
+};
+
+static mut HEARTBEAT_HANDLE: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
+
+/// Set in `late_load`, a clone of the context's for reading the score
+static BACKEND: Mutex<Option<Il2CppBackend>> = Mutex::new(None);
+
+async fn heartbeat_timer(backend: impl GameBackend) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+
+        if let Some(score) = backend.score()? {
+            HUB.broadcast(NowPlayingUpdate::from(score));
+        }
+    }
+}
+
+// You might want to start the timer in your setup_client function:
+// tokio::spawn(async {
+//     let mut interval = tokio::time::interval(Duration::from_secs(1));
+//     loop {
+//         interval.tick().await;
+//         heartbeat_timer_elapsed().await;
+//     }
+// });
+
+#[hook("", "StandardLevelScenesTransitionSetupDataSO", "Init")]
+fn StandardLevelScenesTransitionSetupDataSO_Init(
+    this: &mut StandardLevelScenesTransitionSetupDataSO,
+    game_mode: Gc<Il2CppString>,
+    beatmap_key: BeatmapKey,
+    beatmap_level: Gc<BeatmapLevel>,
+    override_environment_settings: Gc<OverrideEnvironmentSettings>,
+    player_override_color_scheme: Gc<ColorScheme>,
+    player_override_lightshow_colors: bool,
+    beatmap_override_color_scheme: Gc<ColorScheme>,
+    gameplay_modifiers: Gc<GameplayModifiers>,
+    player_specific_settings: Gc<PlayerSpecificSettings>,
+    practice_settings: Gc<PracticeSettings>,
+    environments_list_model: Gc<EnvironmentsListModel>,
+    audio_clip_async_loader: Gc<AudioClipAsyncLoader>,
+    beatmap_data_loader: Gc<BeatmapDataLoader>,
+    settings_manager: Gc<SettingsManager>,
+    back_button_text: Gc<Il2CppString>,
+    beatmap_levels_model: Gc<BeatmapLevelsModel>, // optional
+    beatmap_levels_entitlement_model: Gc<BeatmapLevelsEntitlementModel>, // optional
+    use_test_note_cut_sound_effects: bool,
+    start_paused: bool,
+    recording_tool_data: RecordingToolManager_SetupData, // optional
+) {
+    StandardLevelScenesTransitionSetupDataSO_Init.original(
+        this,
+        game_mode,
+        beatmap_key,
+        beatmap_level,
+        override_environment_settings,
+        player_override_color_scheme,
+        player_override_lightshow_colors,
+        beatmap_override_color_scheme,
+        gameplay_modifiers,
+        player_specific_settings,
+        practice_settings,
+        environments_list_model,
+        audio_clip_async_loader,
+        beatmap_data_loader,
+        settings_manager,
+        back_button_text,
+        beatmap_levels_model,
+        beatmap_levels_entitlement_model,
+        use_test_note_cut_sound_effects,
+        start_paused,
+        recording_tool_data,
+    );
+
+    let now_playing = NowPlaying {
+        level_id: beatmap_key.levelId.to_string_lossy(),
+        is_finished: false,
+    };
+    let level_id = now_playing.level_id.clone();
+    NOW_PLAYING.replace(Some(now_playing.clone())).unwrap();
+    HUB.broadcast(now_playing);
+
+    // looked up here, on the main thread, and read from the timer afterwards
+    let score_controller = Resources::FindObjectsOfTypeAll_1::<Gc<ScoreController>>()
+        .ok()
+        .and_then(|controllers| controllers.as_slice().first().copied());
+    let backend = BACKEND.lock().unwrap().clone();
+    let (Some(mut backend), Some(score_controller)) = (backend, score_controller) else {
+        events::report(
+            Source::Gameplay,
+            Severity::Warning,
+            "No ScoreController found, score updates are disabled",
+            Some(&level_id),
+        );
+        return;
+    };
+
+    backend.set_score_controller(score_controller);
+
+    let handle = RUNTIME.spawn(async move {
+        if let Err(e) = heartbeat_timer(backend).await {
+            events::report(
+                Source::Gameplay,
+                Severity::Error,
+                format!("Score updates stopped: {e:?}"),
+                Some(&level_id),
+            );
+        }
+    });
+
+    unsafe {
+        HEARTBEAT_HANDLE.replace(Some(handle)).unwrap();
+    }
+}
+
+#[hook("", "StandardLevelScenesTransitionSetupDataSO", "Finish")]
+fn StandardLevelScenesTransitionSetupDataSO_Finish(
+    this: &mut StandardLevelScenesTransitionSetupDataSO,
+    level_completion_results: Gc<LevelCompletionResults>,
+) {
+    StandardLevelScenesTransitionSetupDataSO_Finish.original(this, level_completion_results);
+
+    // consume the optional and abort the heartbeat task
+    if let Some(handle) = unsafe { HEARTBEAT_HANDLE.lock().unwrap().take() } {
+        handle.abort();
+    }
+
+    let now_playing = NOW_PLAYING.lock().unwrap().take();
+    if let Some(now_playing) = now_playing {
+        HUB.broadcast(NowPlaying {
+            is_finished: true,
+            ..now_playing
+        });
+    }
+}
+
+#[no_mangle]
+extern "C" fn setup(modinfo: *mut CModInfo) {
+    unsafe {
+        *modinfo = ModInfoBuf {
+            // we have to let the string leak, because the CString is dropped at the end of the function
+            id: MOD_ID.to_string(),
+            version: MOD_VERSION.to_string(),
+            version_long: 0,
+        }
+        .into();
+    }
+
+    quest_hook::setup(MOD_ID);
+}
+
+#[no_mangle]
+extern "C" fn party_panel_on_song_load(levels: *const *const BeatmapLevelPack, len: usize) {
+    if len == 0 || levels.is_null() {
+        return;
+    }
+    // Safety: This function assumes valid pointers and length
+    unsafe {
+        let levels_slice = std::slice::from_raw_parts(levels, len);
+
+        let levels_converted = levels_slice
+            .iter()
+            .map(|level| Gc::from(*level))
+            .flat_map(|level_pack| level_pack._beatmapLevels.as_slice().to_vec())
+            .collect::<Vec<_>>();
+        Il2CppBackend::set_levels(levels_converted);
+
+        match WEB_CONTEXT.get() {
+            Some(web_context) => {
+                web_context.send(WebContextMessage::SongsLoaded);
+            }
+            None => events::report(
+                Source::SongLoading,
+                Severity::Warning,
+                "Songs finished loading before the mod was ready, the song list will be empty",
+                None,
+            ),
+        }
+    }
+}
+
+extern "C" {
+    fn quest_compat_init();
+    pub fn party_panel_run_on_main_thread(
+        func: extern "C" fn(*mut std::ffi::c_void),
+        arg: *mut std::ffi::c_void,
+    );
+}
+
+#[no_mangle]
+extern "C" fn late_load() {
+    StandardLevelScenesTransitionSetupDataSO_Init
+        .install()
+        .unwrap();
+    StandardLevelScenesTransitionSetupDataSO_Finish
+        .install()
+        .unwrap();
+
+    debug!("Setting up SongCore events");
+    unsafe { quest_compat_init() };
+
+    match Application::get_version() {
+        Ok(version) => {
+            GAME_VERSION.get_or_init(|| version.to_string_lossy());
+        }
+        Err(err) => tracing::warn!("Failed to get game version: {:?}", err),
+    }
+
+    debug!("Setting up socket");
+    let player_model = Resources::FindObjectsOfTypeAll_1::<Gc<PlayerDataModel>>()
+        .expect("Failed to find PlayerDataModel 1")
+        .as_slice()
+        .first()
+        .copied()
+        .expect("Failed to find PlayerDataModel 2");
+
+    let backend = Il2CppBackend::new(player_model);
+    BACKEND.replace(Some(backend.clone())).unwrap();
+
+    WEB_CONTEXT.get_or_init(|| {
+        let _guard = RUNTIME.enter();
+        WebContext::new(backend).spawn()
+    });
+
+    RUNTIME.spawn(async move {
+        if let Err(err) = crate::setup_client().await {
+            tracing::error!("Failed to setup client: {:?}", err);
+        }
+    });
+}
@@ -0,0 +1,66 @@
+use futures::{Sink, Stream};
+pub use party_panel_protocol::handshake::Handshake;
+use party_panel_protocol::handshake::{self, Gatekeeper};
+
+use crate::{
+    devices::{self, DEVICES},
+    error::{self, CommandError, ProtocolError},
+    proto::{
+        packets::{Paired, Welcome},
+        Packet,
+    },
+};
+
+/// Panels get in with the configured token or as a paired device, and only
+/// have to authenticate if one of those is set up
+struct Gate<'a> {
+    auth_token: Option<&'a str>,
+}
+
+impl Gatekeeper for Gate<'_> {
+    fn auth_required(&self) -> bool {
+        self.auth_token.is_some() || !DEVICES.is_empty()
+    }
+
+    fn secret(&self, device_id: &str) -> Option<String> {
+        if device_id.is_empty() {
+            self.auth_token.map(str::to_string)
+        } else {
+            DEVICES.secret(device_id)
+        }
+    }
+
+    async fn pair(
+        &self,
+        request_id: u32,
+        pin: &str,
+        device_name: String,
+    ) -> Result<Paired, CommandError> {
+        let device = DEVICES
+            .pair(pin, device_name)
+            .await
+            .map_err(error::command_error)?;
+
+        Ok(devices::paired(request_id, &device))
+    }
+}
+
+pub fn welcome() -> Welcome {
+    handshake::welcome(
+        crate::MOD_VERSION.to_string(),
+        crate::GAME_VERSION.get().cloned().unwrap_or_default(),
+        crate::tls::IDENTITY
+            .get()
+            .map(|identity| identity.fingerprint.clone())
+            .unwrap_or_default(),
+    )
+}
+
+/// See [`handshake::accept`]. With an `auth_token` or any paired devices,
+/// the panel has to authenticate before this returns.
+pub async fn accept<S>(socket: &mut S, auth_token: Option<&str>) -> anyhow::Result<Handshake>
+where
+    S: Stream<Item = Result<Packet, ProtocolError>> + Sink<Packet, Error = ProtocolError> + Unpin,
+{
+    Ok(handshake::accept(socket, welcome(), &Gate { auth_token }).await?)
+}
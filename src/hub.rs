@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+use crate::proto::Packet;
+
+/// How many packets (or batches) can be waiting for a single panel before it
+/// is considered too slow and dropped
+pub const CLIENT_QUEUE_SIZE: usize = 64;
+
+pub static HUB: LazyLock<Hub> = LazyLock::new(Hub::default);
+
+pub type ClientId = u64;
+
+/// What a panel's queue holds
+#[derive(Debug)]
+pub enum Outbound {
+    Packet(Box<Packet>),
+    /// Packets that only take up one slot in the queue, for catching a panel
+    /// up on something as big as the whole song list. The session writes them
+    /// out as fast as the connection takes them.
+    Batch(Vec<Packet>),
+}
+
+pub struct Client {
+    name: String,
+    /// What the panel advertised in its `Hello`
+    capabilities: Vec<String>,
+    /// The paired device the panel authenticated as, if any
+    device_id: Option<String>,
+    /// Set once the panel was caught up on the song list, see [`Hub::mark_synced`]
+    synced: bool,
+    sender: mpsc::Sender<Outbound>,
+}
+
+impl Client {
+    pub fn synced(&self) -> bool {
+        self.synced
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Every connected panel, each with its own outbound queue.
+///
+/// Sending never waits on a socket, so it is fine to call from hooks on the
+/// main thread. The session that registered a client owns the receiving end
+/// and writes whatever shows up to its transport.
+#[derive(Default)]
+pub struct Hub {
+    clients: Mutex<HashMap<ClientId, Client>>,
+    next_id: AtomicU64,
+}
+
+impl Hub {
+    pub fn register(
+        &self,
+        name: String,
+        capabilities: Vec<String>,
+        device_id: Option<String>,
+    ) -> (ClientId, mpsc::Receiver<Outbound>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(CLIENT_QUEUE_SIZE);
+
+        info!("Panel {} registered as client {}", name, id);
+        self.clients.lock().unwrap().insert(
+            id,
+            Client {
+                name,
+                capabilities,
+                device_id,
+                synced: false,
+                sender,
+            },
+        );
+
+        (id, receiver)
+    }
+
+    /// Lets song list broadcasts reach a panel. Called in the same step that
+    /// queues its catch-up, so it never gets a page both ways or out of order.
+    pub fn mark_synced(&self, id: ClientId) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&id) {
+            client.synced = true;
+        }
+    }
+
+    pub fn unregister(&self, id: ClientId) {
+        if let Some(client) = self.clients.lock().unwrap().remove(&id) {
+            info!("Panel {} (client {}) unregistered", client.name, id);
+        }
+    }
+
+    /// Drops every panel authenticated as a revoked device
+    pub fn disconnect_device(&self, device_id: &str) {
+        self.clients.lock().unwrap().retain(|id, client| {
+            let revoked = client.device_id.as_deref() == Some(device_id);
+            if revoked {
+                // dropping the sender ends the session's writer
+                info!(
+                    "Disconnecting panel {} (client {}), its device was revoked",
+                    client.name, id
+                );
+            }
+            !revoked
+        });
+    }
+
+    #[cfg(test)]
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Whether a connected panel advertised `capability`
+    pub fn supports(&self, id: ClientId, capability: &str) -> bool {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|client| client.supports(capability))
+    }
+
+    /// Queues a packet for a single panel
+    pub fn send_to(&self, id: ClientId, packet: impl Into<Packet>) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&id) {
+            if !Self::queue(id, client, Outbound::Packet(Box::new(packet.into()))) {
+                clients.remove(&id);
+            }
+        }
+    }
+
+    /// Queues packets for a single panel in one go, keeping them in order with
+    /// everything else sent to it
+    pub fn send_batch(&self, id: ClientId, packets: Vec<Packet>) {
+        if packets.is_empty() {
+            return;
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&id) {
+            if !Self::queue(id, client, Outbound::Batch(packets)) {
+                clients.remove(&id);
+            }
+        }
+    }
+
+    /// Queues a packet for every connected panel
+    pub fn broadcast(&self, packet: impl Into<Packet>) {
+        let packet = packet.into();
+
+        self.clients.lock().unwrap().retain(|id, client| {
+            Self::queue(*id, client, Outbound::Packet(Box::new(packet.clone())))
+        });
+    }
+
+    /// Queues a packet for the panels `filter` picks, e.g. by capability
+    pub fn broadcast_filtered(&self, filter: impl Fn(&Client) -> bool, packet: impl Into<Packet>) {
+        let packet = packet.into();
+
+        self.clients.lock().unwrap().retain(|id, client| {
+            !filter(client) || Self::queue(*id, client, Outbound::Packet(Box::new(packet.clone())))
+        });
+    }
+
+    /// Returns `false` if the client should be dropped
+    fn queue(id: ClientId, client: &Client, outbound: Outbound) -> bool {
+        match client.sender.try_send(outbound) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                // dropping the sender ends the session's writer
+                warn!(
+                    "Panel {} (client {}) is not keeping up, disconnecting",
+                    client.name, id
+                );
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    use crate::proto::packets::SongListPage;
+
+    fn page(page: u32) -> Packet {
+        SongListPage {
+            page,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    #[test]
+    fn batches_take_one_slot() {
+        let hub = Hub::default();
+        let (id, mut receiver) = hub.register("panel".to_string(), Vec::new(), None);
+
+        // a song list with more pages than the queue has room for
+        let pages = (0..CLIENT_QUEUE_SIZE as u32 * 2).map(page).collect_vec();
+        hub.send_batch(id, pages.clone());
+        for n in 1..CLIENT_QUEUE_SIZE as u32 {
+            hub.send_to(id, page(n));
+        }
+        assert_eq!(hub.client_count(), 1);
+
+        let Ok(Outbound::Batch(batch)) = receiver.try_recv() else {
+            panic!("expected the batch first");
+        };
+        assert_eq!(batch, pages);
+        for n in 1..CLIENT_QUEUE_SIZE as u32 {
+            let Ok(Outbound::Packet(packet)) = receiver.try_recv() else {
+                panic!("expected packet {n}");
+            };
+            assert_eq!(*packet, page(n));
+        }
+
+        // only a full queue drops the panel
+        for n in 0..=CLIENT_QUEUE_SIZE as u32 {
+            hub.send_to(id, page(n));
+        }
+        assert_eq!(hub.client_count(), 0);
+    }
+
+    #[test]
+    fn revoked_devices_are_disconnected() {
+        let hub = Hub::default();
+        let (_, _token) = hub.register("token".to_string(), Vec::new(), None);
+        let (_, _tablet) = hub.register("tablet".to_string(), Vec::new(), Some("a".into()));
+        let (_, _phone) = hub.register("phone".to_string(), Vec::new(), Some("b".into()));
+        let (_, mut tablet_again) =
+            hub.register("tablet".to_string(), Vec::new(), Some("a".into()));
+
+        hub.disconnect_device("a");
+
+        assert_eq!(hub.client_count(), 2);
+        assert!(matches!(
+            tablet_again.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+}
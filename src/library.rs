@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::proto::{
+    items::PreviewBeatmapLevel,
+    packets::{SongsAdded, SongsChanged, SongsRemoved},
+    Packet,
+};
+
+/// How many revisions a reconnecting panel can be behind and still only get
+/// the changes instead of the whole list
+pub const MAX_REVISION_HISTORY: usize = 16;
+
+/// What changed between two revisions of the library
+#[derive(Clone, Debug, Default)]
+pub struct Delta {
+    pub revision: u64,
+    pub previous_revision: u64,
+    pub added: Vec<PreviewBeatmapLevel>,
+    pub removed: Vec<String>,
+    pub changed: Vec<PreviewBeatmapLevel>,
+}
+
+impl Delta {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// `SongsRemoved` and `SongsChanged` if there are any, then `SongsAdded`,
+    /// which is always sent so panels know the revision is complete
+    pub fn packets(&self) -> Vec<Packet> {
+        let mut packets = Vec::with_capacity(3);
+
+        if !self.removed.is_empty() {
+            packets.push(
+                SongsRemoved {
+                    revision: self.revision,
+                    previous_revision: self.previous_revision,
+                    level_ids: self.removed.clone(),
+                }
+                .into(),
+            );
+        }
+        if !self.changed.is_empty() {
+            packets.push(
+                SongsChanged {
+                    revision: self.revision,
+                    previous_revision: self.previous_revision,
+                    levels: self.changed.clone(),
+                }
+                .into(),
+            );
+        }
+        packets.push(
+            SongsAdded {
+                revision: self.revision,
+                previous_revision: self.previous_revision,
+                levels: self.added.clone(),
+            }
+            .into(),
+        );
+
+        packets
+    }
+}
+
+/// The converted levels panels were last told about, with a revision that
+/// goes up whenever a SongCore refresh actually changes something
+pub struct Library {
+    /// Revisions start over every time the mod does, this tells them apart
+    epoch: u64,
+    revision: u64,
+    levels: HashMap<String, PreviewBeatmapLevel>,
+    history: VecDeque<Delta>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            // 0 is what panels without a list send
+            epoch: rand::random::<u64>().max(1),
+            revision: 0,
+            levels: HashMap::new(),
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl Library {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// 0 until the first song list has been converted
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replaces the library with a freshly converted list.
+    /// Returns what changed, or `None` for the first list or if nothing did.
+    pub fn update(&mut self, levels: &[PreviewBeatmapLevel]) -> Option<&Delta> {
+        let mut new_levels = HashMap::with_capacity(levels.len());
+        let mut delta = Delta {
+            revision: self.revision + 1,
+            previous_revision: self.revision,
+            ..Default::default()
+        };
+
+        for level in levels {
+            match self.levels.get(&level.level_id) {
+                None => delta.added.push(level.clone()),
+                Some(old) if old != level => delta.changed.push(level.clone()),
+                Some(_) => {}
+            }
+            new_levels.insert(level.level_id.clone(), level.clone());
+        }
+        delta.removed = self
+            .levels
+            .keys()
+            .filter(|level_id| !new_levels.contains_key(*level_id))
+            .cloned()
+            .collect();
+
+        let first = self.revision == 0;
+        self.levels = new_levels;
+
+        if first {
+            self.revision = 1;
+            return None;
+        }
+        if delta.is_empty() {
+            return None;
+        }
+
+        self.revision = delta.revision;
+        if self.history.len() == MAX_REVISION_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(delta);
+
+        self.history.back()
+    }
+
+    /// Every delta after `revision` of `epoch`, in order. `None` if the panel
+    /// is too far behind (or ahead), or its revision is from an earlier run of
+    /// the mod, and it needs the whole list instead.
+    pub fn changes_since(&self, epoch: u64, revision: u64) -> Option<Vec<&Delta>> {
+        if epoch != self.epoch {
+            return None;
+        }
+        if revision == self.revision {
+            return Some(Vec::new());
+        }
+
+        let start = self
+            .history
+            .iter()
+            .position(|delta| delta.previous_revision == revision)?;
+
+        Some(self.history.range(start..).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(id: &str, name: &str) -> PreviewBeatmapLevel {
+        PreviewBeatmapLevel {
+            level_id: id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(levels: &[PreviewBeatmapLevel]) -> Vec<&str> {
+        levels.iter().map(|level| level.level_id.as_str()).collect()
+    }
+
+    #[test]
+    fn first_list_has_no_delta() {
+        let mut library = Library::default();
+        assert_eq!(library.revision(), 0);
+
+        assert!(library.update(&[level("a", "A")]).is_none());
+        assert_eq!(library.revision(), 1);
+        assert!(library
+            .changes_since(library.epoch(), 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn diffs_added_removed_and_changed() {
+        let mut library = Library::default();
+        library.update(&[level("a", "A"), level("b", "B"), level("c", "C")]);
+
+        let delta = library
+            .update(&[level("a", "A"), level("b", "Renamed"), level("d", "D")])
+            .unwrap()
+            .clone();
+        assert_eq!((delta.previous_revision, delta.revision), (1, 2));
+        assert_eq!(ids(&delta.added), ["d"]);
+        assert_eq!(delta.removed, ["c"]);
+        assert_eq!(ids(&delta.changed), ["b"]);
+        assert_eq!(delta.changed[0].name, "Renamed");
+
+        let [Packet::SongsRemoved(_), Packet::SongsChanged(_), Packet::SongsAdded(_)] =
+            &delta.packets()[..]
+        else {
+            panic!("unexpected packets {:?}", delta.packets());
+        };
+    }
+
+    #[test]
+    fn unchanged_lists_keep_the_revision() {
+        let mut library = Library::default();
+        library.update(&[level("a", "A")]);
+
+        assert!(library.update(&[level("a", "A")]).is_none());
+        assert_eq!(library.revision(), 1);
+    }
+
+    #[test]
+    fn changes_since_chains_deltas() {
+        let mut library = Library::default();
+        library.update(&[]);
+        library.update(&[level("a", "A")]);
+        library.update(&[level("a", "A"), level("b", "B")]);
+        library.update(&[level("b", "B")]);
+        let epoch = library.epoch();
+
+        let deltas = library.changes_since(epoch, 1).unwrap();
+        let revisions: Vec<_> = deltas
+            .iter()
+            .map(|delta| (delta.previous_revision, delta.revision))
+            .collect();
+        assert_eq!(revisions, [(1, 2), (2, 3), (3, 4)]);
+        assert_eq!(library.changes_since(epoch, 3).unwrap().len(), 1);
+        assert!(library.changes_since(epoch, 4).unwrap().is_empty());
+
+        // from the future
+        assert!(library.changes_since(epoch, 9).is_none());
+    }
+
+    #[test]
+    fn other_epochs_need_the_whole_list() {
+        let mut library = Library::default();
+        library.update(&[]);
+        library.update(&[level("a", "A")]);
+
+        let restarted = Library::default();
+        assert_ne!(library.epoch(), restarted.epoch());
+        assert!(library.changes_since(restarted.epoch(), 1).is_none());
+        assert!(library.changes_since(0, 0).is_none());
+    }
+
+    #[test]
+    fn evicted_revisions_need_the_whole_list() {
+        let mut library = Library::default();
+        library.update(&[]);
+        for n in 0..=MAX_REVISION_HISTORY {
+            library.update(&[level(&n.to_string(), "")]);
+        }
+        let epoch = library.epoch();
+        assert_eq!(library.revision(), MAX_REVISION_HISTORY as u64 + 2);
+
+        // revision 1 -> 2 was pushed out by the newest delta
+        assert!(library.changes_since(epoch, 1).is_none());
+        let deltas = library.changes_since(epoch, 2).unwrap();
+        assert_eq!(deltas.len(), MAX_REVISION_HISTORY);
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Context;
+use futures::{SinkExt, StreamExt};
+
+use party_panel_protocol::capabilities;
+
+use crate::{
+    config::Config,
+    devices,
+    error::ProtocolError,
+    handshake::{self, Handshake},
+    heartbeat::{self, Heartbeat},
+    hub::{ClientId, Outbound, HUB},
+    proto::Packet,
+    transport::Transport,
+    web_context::{WebContextHandle, WebContextMessage},
+    NOW_PLAYING, WEB_CONTEXT,
+};
+
+/// Handshakes with a connected panel and serves it until it disconnects.
+///
+/// Reading and writing run independently: outbound packets come from the
+/// panel's queue in the [`HUB`], inbound packets are handed to the
+/// [`WEB_CONTEXT`] actor, which applies them one at a time no matter which
+/// panel sent them.
+///
+/// Panels that support heartbeats get one every `heartbeat_interval` and are
+/// disconnected if they send nothing for `heartbeat_timeout`.
+pub async fn run(mut socket: Transport, config: &Config) -> anyhow::Result<()> {
+    let (peer, device_id, first_packet) =
+        match handshake::accept(&mut socket, config.auth_token()).await? {
+            Handshake::Accepted { hello, device_id } => (Some(hello), device_id, None),
+            Handshake::Legacy(packet) => (None, None, packet),
+        };
+
+    let (name, capabilities) = peer
+        .map(|hello| {
+            (
+                hello.client_name,
+                capabilities::effective(hello.capabilities),
+            )
+        })
+        .unwrap_or_else(|| ("legacy panel".to_string(), Vec::new()));
+
+    let compress = capabilities
+        .iter()
+        .any(|capability| capability == capabilities::COMPRESSION_CAPABILITY);
+    socket.codec.set_compression(compress);
+    let heartbeats = capabilities
+        .iter()
+        .any(|capability| capability == capabilities::HEARTBEAT_CAPABILITY);
+    let web_context = WEB_CONTEXT
+        .get()
+        .context("WebContext is not running")?
+        .clone();
+
+    let (client_id, mut outbound) = HUB.register(name, capabilities, device_id);
+    sync_client(&web_context, client_id);
+
+    let Transport {
+        mut reader,
+        mut writer,
+        ..
+    } = socket;
+
+    let mut writer_task = tokio::spawn(async move {
+        while let Some(outbound) = outbound.recv().await {
+            match outbound {
+                Outbound::Packet(packet) => writer.send(*packet).await?,
+                Outbound::Batch(packets) => {
+                    for packet in packets {
+                        writer.feed(packet).await?;
+                    }
+                    writer.flush().await?;
+                }
+            }
+        }
+
+        anyhow::Ok(())
+    });
+
+    let mut heartbeat = Heartbeat::new(config.heartbeat_interval(), config.heartbeat_timeout());
+    let mut ticker = tokio::time::interval(heartbeat.interval());
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let read_loop = async {
+        if let Some(packet) = first_packet {
+            web_context.send(WebContextMessage::Packet {
+                client_id,
+                packet: Box::new(packet),
+            });
+        }
+
+        loop {
+            let deadline = heartbeat.deadline().filter(|_| heartbeats);
+            // not polled without a deadline
+            let quiet =
+                tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now));
+
+            let packet = tokio::select! {
+                packet = reader.next() => packet,
+                _ = ticker.tick(), if heartbeats => {
+                    HUB.send_to(client_id, heartbeat.ping());
+                    continue;
+                }
+                _ = quiet, if deadline.is_some() => {
+                    let timeout = heartbeat.timeout().unwrap_or_default();
+                    return Err(ProtocolError::PeerTimeout(timeout).into());
+                }
+            };
+            let Some(packet) = packet else {
+                break;
+            };
+            let packet = packet?;
+            heartbeat.received();
+
+            // answered here so a busy game can't delay them
+            if let Packet::Command(command) = &packet {
+                if heartbeat::is_heartbeat(command) {
+                    if let Some(echo) = heartbeat.handle(command) {
+                        HUB.send_to(client_id, echo);
+                    }
+                    continue;
+                }
+            }
+
+            // pairing is about the connection, not the game
+            if devices::is_device_packet(&packet) {
+                devices::handle(client_id, packet).await;
+                continue;
+            }
+
+            web_context.send(WebContextMessage::Packet {
+                client_id,
+                packet: Box::new(packet),
+            });
+        }
+
+        anyhow::Ok(())
+    };
+
+    let result = tokio::select! {
+        result = read_loop => result,
+        result = &mut writer_task => result?,
+    };
+
+    HUB.unregister(client_id);
+    writer_task.abort();
+
+    result
+}
+
+/// Sends what a fresh (or reconnected) panel missed: the song list and what is
+/// playing
+fn sync_client(web_context: &WebContextHandle, client_id: ClientId) {
+    web_context.send(WebContextMessage::SyncClient(client_id));
+
+    let now_playing = NOW_PLAYING.lock().unwrap().clone();
+    if let Some(now_playing) = now_playing {
+        HUB.send_to(client_id, now_playing);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tracing::{info, warn};
+
+use crate::{config::Config, session, transport};
+
+/// Delay before the first reconnect attempt
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for the delay between attempts
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long a session has to last before the backoff starts over. A panel
+/// that accepts the connection and then refuses the handshake keeps backing off.
+pub const MIN_STABLE_SESSION: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter, so a room full of headsets doesn't
+/// hammer a restarted panel all at once
+pub struct Backoff {
+    current: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            current: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before the next attempt, somewhere between half and
+    /// all of the current delay
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(MAX_BACKOFF);
+
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    pub fn reset(&mut self) {
+        self.current = INITIAL_BACKOFF;
+    }
+}
+
+/// Keeps dialing out to the panel at `config.addr`, reconnecting whenever
+/// the connection fails or drops
+pub async fn run(config: Arc<Config>) -> anyhow::Result<()> {
+    let mut backoff = Backoff::default();
+
+    loop {
+        match transport::connect(&config).await {
+            Ok(socket) => {
+                info!("Connected to panel at {}", config.addr);
+                let connected_at = Instant::now();
+
+                match session::run(socket, &config).await {
+                    Ok(()) => info!("Panel at {} disconnected", config.addr),
+                    Err(err) => warn!("Panel at {} disconnected: {:?}", config.addr, err),
+                }
+
+                if connected_at.elapsed() >= MIN_STABLE_SESSION {
+                    backoff.reset();
+                }
+            }
+            Err(err) => warn!("Failed to connect to panel at {}: {:?}", config.addr, err),
+        }
+
+        let delay = backoff.next_delay();
+        info!("Reconnecting in {:?}", delay);
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fmt::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    client,
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    server, TlsAcceptor, TlsConnector,
+};
+use tracing::info;
+
+use crate::config;
+
+/// Names baked into the generated certificate. Panels are expected to pin the
+/// fingerprint rather than check these.
+const SUBJECT_ALT_NAMES: &[&str] = &["party-panel", "localhost"];
+
+pub static IDENTITY: OnceLock<Identity> = OnceLock::new();
+
+/// The mod's certificate and what panels need to trust it.
+///
+/// Whoever dialed is the TLS client: panels that connect to the mod pin its
+/// fingerprint, and when the mod dials out it pins the panel's instead while
+/// still presenting its own certificate.
+pub struct Identity {
+    acceptor: TlsAcceptor,
+    cert: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+    provider: Arc<CryptoProvider>,
+    /// SHA-256 of the certificate, as colon separated hex
+    pub fingerprint: String,
+}
+
+impl Identity {
+    /// In the game's private data, since the key must not be readable by
+    /// other apps the way everything on `/sdcard` is
+    pub fn dir() -> PathBuf {
+        config::data_dir().join("tls")
+    }
+
+    /// Loads the certificate from a previous run or makes a new one
+    pub async fn load_or_generate() -> anyhow::Result<Self> {
+        let dir = Self::dir();
+        let cert_path = dir.join("cert.der");
+        let key_path = dir.join("key.der");
+
+        let (cert, key) = if tokio::fs::try_exists(&cert_path).await? {
+            let cert = tokio::fs::read(&cert_path)
+                .await
+                .context("TLS certificate unable to be loaded")?;
+            let key = tokio::fs::read(&key_path)
+                .await
+                .context("TLS key unable to be loaded")?;
+            (cert, key)
+        } else {
+            info!("Generating TLS certificate");
+            let names = SUBJECT_ALT_NAMES.iter().map(|name| name.to_string());
+            let generated = rcgen::generate_simple_self_signed(names.collect::<Vec<_>>())
+                .context("Failed to generate TLS certificate")?;
+
+            let cert = generated.cert.der().to_vec();
+            let key = generated.key_pair.serialize_der();
+
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(&cert_path, &cert).await?;
+            config::write_private(&key_path, &key).await?;
+            (cert, key)
+        };
+
+        let fingerprint = fingerprint(&cert);
+        // so it can be read off the headset without digging through logs
+        let config_dir = config::config_dir();
+        tokio::fs::create_dir_all(&config_dir).await?;
+        tokio::fs::write(config_dir.join("tls_fingerprint.txt"), &fingerprint).await?;
+
+        let cert = CertificateDer::from(cert);
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key));
+
+        let provider = Arc::new(crypto::ring::default_provider());
+        let server_config = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key.clone_key())
+            .context("Invalid TLS certificate")?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            cert,
+            key,
+            provider,
+            fingerprint,
+        })
+    }
+
+    /// Runs the server side of the TLS handshake for a panel that dialed in
+    pub async fn accept<S>(&self, stream: S) -> anyhow::Result<server::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.acceptor
+            .accept(stream)
+            .await
+            .context("TLS handshake failed")
+    }
+
+    /// Runs the client side of the TLS handshake with a panel the mod dialed,
+    /// refusing it unless its certificate has `panel_fingerprint`
+    pub async fn connect<S>(
+        &self,
+        stream: S,
+        ip: IpAddr,
+        panel_fingerprint: &str,
+    ) -> anyhow::Result<client::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let verifier = PinnedCert {
+            fingerprint: panel_fingerprint.to_string(),
+            provider: self.provider.clone(),
+        };
+        let client_config = rustls::ClientConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_client_auth_cert(vec![self.cert.clone()], self.key.clone_key())
+            .context("Invalid TLS certificate")?;
+
+        TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::from(ip), stream)
+            .await
+            .context("TLS handshake failed")
+    }
+}
+
+/// Trusts the panel's certificate by its fingerprint alone, since panels are
+/// self-signed as well and there is no CA both ends know
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity).eq_ignore_ascii_case(self.fingerprint.trim()) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Panel certificate doesn't match panel_fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// `AB:CD:...` SHA-256 of a DER certificate, the form most tools print
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .enumerate()
+        .fold(String::new(), |mut out, (i, byte)| {
+            if i > 0 {
+                out.push(':');
+            }
+            let _ = write!(out, "{byte:02X}");
+            out
+        })
+}
+
+/// Sets up [`IDENTITY`] and logs the fingerprint panels should pin
+pub async fn init() -> anyhow::Result<&'static Identity> {
+    if let Some(identity) = IDENTITY.get() {
+        return Ok(identity);
+    }
+
+    let identity = Identity::load_or_generate().await?;
+    let identity = IDENTITY.get_or_init(|| identity);
+    info!("TLS certificate fingerprint: {}", identity.fingerprint);
+
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both ends use the same identity, so the mod pins its own fingerprint
+    async fn handshake(panel_fingerprint: &str) -> anyhow::Result<()> {
+        let identity = init().await?;
+        let (mod_end, panel_end) = tokio::io::duplex(16 * 1024);
+
+        let (connected, accepted) = tokio::join!(
+            identity.connect(mod_end, IpAddr::from([127, 0, 0, 1]), panel_fingerprint),
+            identity.accept(panel_end),
+        );
+        connected?;
+        accepted?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connects_to_pinned_panel() {
+        let identity = init().await.unwrap();
+
+        handshake(&identity.fingerprint.to_lowercase())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_other_panels() {
+        let other = fingerprint(b"some other certificate");
+
+        assert!(handshake(&other).await.is_err());
+    }
+}
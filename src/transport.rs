@@ -0,0 +1,171 @@
+use std::net::SocketAddr;
+
+use anyhow::Context as _;
+pub use party_panel_protocol::transport::Transport;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpSocket, TcpStream},
+};
+
+use crate::{codec::PartyPanelCodec, config::Config, tls};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// "moon" frames straight over TCP
+    #[default]
+    Tcp,
+    /// "moon" frames inside binary WebSocket messages, or JSON packets inside
+    /// text messages, for browser panels
+    WebSocket,
+}
+
+/// Puts the configured transport on top of a connected stream.
+/// `ws_url` is set when the mod dialed out and is the WebSocket client.
+async fn wrap<S>(stream: S, config: &Config, ws_url: Option<String>) -> anyhow::Result<Transport>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let codec = PartyPanelCodec::with_max_frame_size(config.max_frame_size);
+
+    match (config.transport, ws_url) {
+        (TransportKind::Tcp, _) => Ok(Transport::from_io(stream, codec)),
+        (TransportKind::WebSocket, Some(url)) => {
+            let (ws, _response) = tokio_tungstenite::client_async(url, stream)
+                .await
+                .context("WebSocket handshake failed")?;
+
+            Ok(Transport::from_websocket(ws, codec))
+        }
+        (TransportKind::WebSocket, None) => {
+            let ws = tokio_tungstenite::accept_async(stream)
+                .await
+                .context("WebSocket handshake failed")?;
+
+            Ok(Transport::from_websocket(ws, codec))
+        }
+    }
+}
+
+/// Runs the TLS handshake first if enabled, then [`wrap`]s the stream.
+/// `dialed` is set when the mod dialed out and is the TLS client.
+async fn secure(
+    stream: TcpStream,
+    config: &Config,
+    dialed: Option<SocketAddr>,
+) -> anyhow::Result<Transport> {
+    if !config.tls {
+        let ws_url = dialed.map(|addr| format!("ws://{addr}/"));
+        return wrap(stream, config, ws_url).await;
+    }
+
+    let identity = tls::IDENTITY
+        .get()
+        .context("TLS is enabled but not set up")?;
+
+    match dialed {
+        Some(addr) => {
+            let panel_fingerprint = config
+                .panel_fingerprint()
+                .context("TLS in connect mode needs panel_fingerprint set")?;
+            let stream = identity
+                .connect(stream, addr.ip(), panel_fingerprint)
+                .await?;
+            wrap(stream, config, Some(format!("wss://{addr}/"))).await
+        }
+        None => {
+            let stream = identity.accept(stream).await?;
+            wrap(stream, config, None).await
+        }
+    }
+}
+
+/// Dials out to the panel at `config.addr`
+pub async fn connect(config: &Config) -> anyhow::Result<Transport> {
+    let addr = config
+        .addr
+        .parse()
+        .context("Config addr is not a valid socket address")?;
+
+    let socket = TcpSocket::new_v4()?;
+    let stream = socket.connect(addr).await?;
+
+    secure(stream, config, Some(addr)).await
+}
+
+/// Wraps a panel connection accepted in listen mode
+pub async fn accept(stream: TcpStream, config: &Config) -> anyhow::Result<Transport> {
+    secure(stream, config, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{client_async, tungstenite::Message, WebSocketStream};
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+    use crate::proto::{packets::StartPairing, Packet};
+
+    /// A browser-style client connected to a mod listening on loopback
+    async fn connected() -> (WebSocketStream<TcpStream>, Transport) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            transport: TransportKind::WebSocket,
+            ..Default::default()
+        };
+
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (ws, _response) = client_async(format!("ws://{addr}/"), stream).await.unwrap();
+            ws
+        };
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept(stream, &config).await.unwrap()
+        };
+
+        tokio::join!(client, server)
+    }
+
+    fn packet() -> Packet {
+        StartPairing { request_id: 7 }.into()
+    }
+
+    #[tokio::test]
+    async fn round_trips_binary_frames() {
+        let (mut ws, mut transport) = connected().await;
+
+        let mut frame = BytesMut::new();
+        PartyPanelCodec::new().encode(packet(), &mut frame).unwrap();
+        ws.send(Message::Binary(frame.clone().freeze()))
+            .await
+            .unwrap();
+        assert_eq!(transport.next().await.unwrap().unwrap(), packet());
+
+        transport.send(packet()).await.unwrap();
+        let Some(Ok(Message::Binary(reply))) = ws.next().await else {
+            panic!("expected a binary message");
+        };
+        assert_eq!(reply, frame);
+    }
+
+    #[tokio::test]
+    async fn round_trips_json_text() {
+        let (mut ws, mut transport) = connected().await;
+
+        let json = serde_json::to_string(&packet()).unwrap();
+        ws.send(Message::text(json.clone())).await.unwrap();
+        assert_eq!(transport.next().await.unwrap().unwrap(), packet());
+
+        transport.send(packet()).await.unwrap();
+        let Some(Ok(Message::Text(reply))) = ws.next().await else {
+            panic!("expected a text message");
+        };
+        assert_eq!(reply.as_str(), json);
+    }
+}
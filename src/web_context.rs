@@ -1,721 +1,868 @@
-use anyhow::{anyhow, Context};
-use bs_cordl::{
-    GlobalNamespace::{
-        AdditionalContentModel, BeatmapCharacteristicSO, BeatmapDifficulty, BeatmapKey,
-        BeatmapLevel, BeatmapLevelsModel, EntitlementStatus, GameplayModifiers,
-        GameplayModifiers_EnabledObstacleType, GameplayModifiers_EnergyType,
-        GameplayModifiers_SongSpeed, MainFlowCoordinator, MenuTransitionsHelper, PlayerData,
-        PlayerDataModel, PracticeSettings, SoloFreePlayFlowCoordinator,
-        StandardLevelReturnToMenuController,
-    },
-    System::{
-        self, Nullable_1,
-        Threading::{CancellationToken, CancellationTokenSource},
-    },
-    UnityEngine::Resources,
-    HMUI::NoTransitionsButton,
-};
-use bytes::{Buf, Bytes, BytesMut};
-use futures::{
-    future::{self},
-    TryStreamExt,
-};
+use futures::future::{self};
 use itertools::Itertools;
-use prost::Message;
-use quest_hook::libil2cpp::{Gc, Il2CppString};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
-use tracing::info;
+use party_panel_protocol::capabilities::{SONG_LIST_DELTAS_CAPABILITY, SONG_LIST_PAGES_CAPABILITY};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
 
 use crate::{
-    async_utils::Il2CPPFutureAwaitable,
-    party_panel_run_on_main_thread,
+    backend::GameBackend,
+    error::{ack, CommandError},
+    events,
+    hub::{Client, ClientId, HUB},
+    library::{Delta, Library},
     proto::{
-        self,
         items::PreviewBeatmapLevel,
-        packets::{Command, DownloadSong, PlaySong, SongList},
-        CommandType, PacketType, PartyPacket,
+        packets::{
+            error_event::{Severity, Source},
+            LevelError, SongList, SongListBegin, SongListEnd, SongListPage,
+        },
+        v2::{self, Difficulty},
+        CommandType, Packet, PacketType,
     },
 };
 
-pub struct WebContext {
-    pub songs: Vec<SongData>,
-    pub player_data: Gc<PlayerDataModel>,
-    pub level_cancellation_token_source: Option<Gc<CancellationTokenSource>>,
-    pub get_status_cancellation_token_source: Option<Gc<CancellationTokenSource>>,
-    pub flow: Option<Gc<SoloFreePlayFlowCoordinator>>,
-    pub socket: TcpStream, //WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+/// Levels per [`SongListPage`], so panels can show a big library bit by bit.
+/// Panels that connect later get every page so far in one
+/// [`Outbound::Batch`](crate::hub::Outbound::Batch), however many there are.
+pub const SONG_LIST_PAGE_SIZE: usize = 200;
+
+pub struct WebContext<B: GameBackend> {
+    pub songs: Vec<SongData<B::Level>>,
+    /// Begin, pages and end of the newest complete song list, replayed to
+    /// panels that connect afterwards
+    pub song_list_pages: Vec<Packet>,
+    /// The same for a list that is still being converted
+    pub pending_song_list_pages: Vec<Packet>,
+    /// What panels taking deltas were last told about
+    pub library: Library,
+    /// Bumped on every [`WebContext::update`] so stale conversions can be dropped
+    pub song_list_generation: u64,
+    pub backend: B,
 }
 
-pub struct SongData {
+#[derive(Clone)]
+pub struct SongData<L> {
     pub hash: SongId,
-    pub level: Gc<BeatmapLevel>,
+    pub level: L,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SongId(pub String);
 
-impl WebContext {
-    pub async fn read_loop(&mut self) -> anyhow::Result<()> {
-        let mut buf: BytesMut = BytesMut::with_capacity(1024);
+pub enum WebContextMessage {
+    /// A packet sent by a panel
+    Packet {
+        client_id: ClientId,
+        packet: Box<Packet>,
+    },
+    /// The game finished (re)loading levels, see [`GameBackend::levels`]
+    SongsLoaded,
+    /// Part of a song list being converted, tagged with the [`WebContext::update`] that started it
+    SongListPacket {
+        generation: u64,
+        packet: Box<Packet>,
+    },
+    /// A panel just connected and needs to catch up
+    SyncClient(ClientId),
+}
 
-        loop {
-            // buffer the size of UTF8 "moon"
-            let mut header = [0u8; 4];
-            self.socket.read_exact(&mut header).await?;
+/// The only way to reach the [`WebContext`] once it is running.
+///
+/// Sending never blocks, so hooks on the main thread and panel sessions can
+/// use it freely.
+#[derive(Clone)]
+pub struct WebContextHandle(mpsc::UnboundedSender<WebContextMessage>);
+
+impl WebContextHandle {
+    pub fn send(&self, message: WebContextMessage) {
+        if self.0.send(message).is_err() {
+            warn!("WebContext is not running, dropping message");
+        }
+    }
+}
 
-            // continue? restart loop
-            if header != "moon".as_bytes() {
-                return Err(anyhow!("Invalid header"));
-            }
+impl<B: GameBackend> WebContext<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            songs: Default::default(),
+            song_list_pages: Vec::new(),
+            pending_song_list_pages: Vec::new(),
+            library: Library::default(),
+            song_list_generation: 0,
+            backend,
+        }
+    }
 
-            let packet_type: PacketType = self.socket.read_i32().await?.into();
+    /// Moves the context into its own task, which handles messages one at a time.
+    /// Must be called from within the runtime.
+    pub fn spawn(self) -> WebContextHandle {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let handle = WebContextHandle(sender);
 
-            let len = self.socket.read_u64().await? as usize;
-            // resize to read the amount we need
-            buf.resize(len, 0);
+        tokio::spawn(self.run(receiver, handle.clone()));
 
-            let buffer = self.socket.read_exact(&mut buf).await?;
-            if buffer < len {
-                return Err(anyhow!("Failed to read the full buffer"));
-            }
+        handle
+    }
 
-            let error = self.parse_packet(packet_type, buf.copy_to_bytes(len)).await;
-            if let Err(e) = error {
-                info!("Error parsing packet: {:?}", e);
+    async fn run(
+        mut self,
+        mut receiver: mpsc::UnboundedReceiver<WebContextMessage>,
+        handle: WebContextHandle,
+    ) {
+        while let Some(message) = receiver.recv().await {
+            match message {
+                WebContextMessage::Packet { client_id, packet } => {
+                    let request_id = packet.request_id();
+
+                    let result = match *packet {
+                        Packet::SyncSongs(sync) => {
+                            self.sync_songs(client_id, sync.library_epoch, sync.since_revision);
+                            Ok(())
+                        }
+                        packet => self.parse_packet(packet).await,
+                    };
+                    if let Err(e) = &result {
+                        info!("Error parsing packet from client {}: {:?}", client_id, e);
+                    }
+
+                    if request_id != 0 {
+                        HUB.send_to(client_id, ack(request_id, &result));
+                    } else if let Err(e) = result {
+                        // no Ack to carry it, tell the panel anyway
+                        events::report_to(client_id, Source::Command, Severity::Error, e);
+                    }
+                }
+                WebContextMessage::SongsLoaded => {
+                    if let Err(e) = self.load_songs(handle.clone()) {
+                        events::report(
+                            Source::SongList,
+                            Severity::Error,
+                            format!("Failed to update song list: {e:?}"),
+                            None,
+                        );
+                    }
+                }
+                WebContextMessage::SongListPacket { generation, packet } => {
+                    // a newer update superseded this one
+                    if generation != self.song_list_generation {
+                        continue;
+                    }
+
+                    self.song_list_packet(*packet);
+                }
+                WebContextMessage::SyncClient(client_id) => self.sync_client(client_id),
             }
         }
+    }
 
-        Ok(())
+    /// Takes the backend's levels and starts converting them
+    fn load_songs(&mut self, handle: WebContextHandle) -> anyhow::Result<()> {
+        self.songs = self
+            .backend
+            .levels()?
+            .into_iter()
+            .map(|level| SongData {
+                hash: SongId(self.backend.level_id(&level)),
+                level,
+            })
+            .collect();
+
+        self.update(handle)
     }
 
-    pub async fn update(&mut self) -> anyhow::Result<()> {
-        let player_data = self.player_data.clone();
+    /// Starts converting the current songs in the background. Each page comes
+    /// back as a [`WebContextMessage::SongListPacket`] as soon as it is done, so
+    /// panels can show levels early and packets keep flowing meanwhile.
+    pub fn update(&mut self, handle: WebContextHandle) -> anyhow::Result<()> {
+        self.backend.cancel_lookups()?;
+        let backend = self.backend.clone();
+
+        self.song_list_generation += 1;
+        let generation = self.song_list_generation;
+
+        let total_levels = self.songs.len();
+        // owned pages, so the task doesn't need levels to be Sync
+        let pages = self
+            .songs
+            .chunks(SONG_LIST_PAGE_SIZE)
+            .map(<[_]>::to_vec)
+            .collect_vec();
+        tokio::spawn(async move {
+            let send = |packet: Packet| {
+                handle.send(WebContextMessage::SongListPacket {
+                    generation,
+                    packet: Box::new(packet),
+                })
+            };
 
-        if let Some(mut source) = self.get_status_cancellation_token_source {
-            source.Cancel_0()?;
-        }
-        self.get_status_cancellation_token_source = Some(CancellationTokenSource::New_0()?);
-
-        let token = self
-            .get_status_cancellation_token_source
-            .unwrap()
-            .get_Token()?;
-
-        let mut level_futures = Vec::with_capacity(self.songs.len());
-        let levels = self.songs.iter().map(|s| s.level.clone()).collect_vec();
-        for level in levels {
-            let preview_level =
-                Self::convert_to_packet_type(level, player_data._playerData, Some(token.clone()));
-            level_futures.push(preview_level);
-        }
+            send(
+                SongListBegin {
+                    list_id: generation,
+                    total_levels: total_levels as u32,
+                    page_size: SONG_LIST_PAGE_SIZE as u32,
+                }
+                .into(),
+            );
+
+            let mut end = SongListEnd {
+                list_id: generation,
+                ..Default::default()
+            };
+            let mut processed_levels = 0;
+
+            for (page, chunk) in pages.into_iter().enumerate() {
+                let chunk_len = chunk.len();
+                let results = future::join_all(chunk.into_iter().map(|song| {
+                    let backend = backend.clone();
+                    async move { (song.hash, preview(backend, song.level).await) }
+                }))
+                .await;
+
+                let mut page = SongListPage {
+                    list_id: generation,
+                    page: page as u32,
+                    ..Default::default()
+                };
+                for (SongId(level_id), result) in results {
+                    match result {
+                        Ok(preview) => page.levels.push(preview),
+                        Err(e) => {
+                            // one broken level shouldn't cost the panel the whole library
+                            warn!("Failed to convert level {}: {:?}", level_id, e);
+                            page.errors.push(LevelError {
+                                level_id,
+                                message: format!("{e:?}"),
+                            });
+                        }
+                    }
+                }
 
-        let levels = future::try_join_all(level_futures).await?;
+                processed_levels += chunk_len as u32;
+                page.processed_levels = processed_levels;
+                end.pages += 1;
+                end.levels += page.levels.len() as u32;
+                end.failed_levels += page.errors.len() as u32;
 
-        self.write_packet(SongList { levels }).await?;
+                send(page.into());
+            }
+
+            if end.failed_levels > 0 {
+                events::report(
+                    Source::SongList,
+                    Severity::Warning,
+                    format!("{} levels could not be converted", end.failed_levels),
+                    None,
+                );
+            }
+            send(end.into());
+        });
 
         Ok(())
     }
 
-    ///
-    ///
-    ///
-    async fn parse_packet(&mut self, packet_type: PacketType, data: Bytes) -> anyhow::Result<()> {
-        /*
-                   if (packet.Type == PacketType.PlaySong)
-           {
-               PlaySong playSong = packet.SpecificPacket as PlaySong;
-
-               var desiredLevel = Plugin.masterLevelList.First(x => x.levelID == playSong.levelId);
-               var desiredCharacteristic = desiredLevel.previewDifficultyBeatmapSets.Select(level => level.beatmapCharacteristic).First(x => x.serializedName == playSong.characteristic.Name);
-               BeatmapDifficulty desiredDifficulty;
-               playSong.difficulty.BeatmapDifficultyFromSerializedName(out desiredDifficulty);
-
-               SaberUtilities.PlaySong(desiredLevel, desiredCharacteristic, desiredDifficulty, playSong);
-           }
-           else if (packet.Type == PacketType.Command)
-           {
-               Command command = packet.SpecificPacket as Command;
-               if (command.commandType == Command.CommandType.ReturnToMenu)
-               {
-                   SaberUtilities.ReturnToMenu();
-               }
-           }
-           else if (packet.Type == PacketType.DownloadSong)
-           {
-               DownloadSong download = packet.SpecificPacket as DownloadSong;
-
-               Task.Run(async () => { await BeatSaverDownloader.Misc.SongDownloader.Instance.DownloadSong(Plugin.Client.Beatmap(download.songKey).Result, CancellationToken.None); SongCore.Loader.Instance.RefreshSongs(); });
-           }
-        */
-
-        match packet_type {
-            PacketType::PlaySong => {
-                let playsong = PlaySong::decode(data)?;
-                let desired_level = self
-                    .songs
-                    .iter()
-                    .find(|x| x.hash.0 == playsong.level_id)
-                    .ok_or_else(|| anyhow!("Level not found"))?;
-
-                let desired_characteristic = self
-                    .player_data
-                    ._playerDataFileModel
-                    ._beatmapCharacteristicCollection
-                    .GetBeatmapCharacteristicBySerializedName(Il2CppString::new(
-                        &playsong.characteristic.as_ref().unwrap().name,
-                    ))
-                    .context(anyhow!("Characteristic not found"))?;
-
-                let desired_diff = difficulty_from_name(&playsong.difficulty);
-                self.play_song(
-                    desired_level.level,
-                    desired_characteristic,
-                    desired_diff,
-                    &playsong,
-                )
-                .await?;
-            }
-            PacketType::Command => {
-                let command = Command::decode(data)?;
-                let command_type = CommandType::from(command.command_type);
-                if let CommandType::ReturnToMenu = command_type {
-                    self.return_to_menu();
-                    // return to menu
-                }
-            }
-            PacketType::DownloadSong => {
-                let download_song = DownloadSong::decode(data)?;
-                // TODO: download song
+    /// Sends a finished part of the song list to the panels that take pages,
+    /// and the whole list to the rest once it is complete. Panels that take
+    /// deltas only get the whole list the first time, then just what changed.
+    fn song_list_packet(&mut self, mut packet: Packet) {
+        let library_known = self.library.revision() > 0;
+        // panels that haven't been caught up yet get everything in their sync
+        let wants_full_list = |client: &Client| {
+            client.synced() && !(library_known && client.supports(SONG_LIST_DELTAS_CAPABILITY))
+        };
+
+        let mut delta = None;
+        match &mut packet {
+            Packet::SongListBegin(_) => self.pending_song_list_pages.clear(),
+            Packet::SongListEnd(end) => {
+                let levels = page_levels(&self.pending_song_list_pages)
+                    .cloned()
+                    .collect_vec();
+                delta = self.library.update(&levels).cloned();
+                end.revision = self.library.revision();
+                end.library_epoch = self.library.epoch();
             }
             _ => {}
         }
+        let done = matches!(packet, Packet::SongListEnd(_));
+
+        HUB.broadcast_filtered(
+            |client| client.supports(SONG_LIST_PAGES_CAPABILITY) && wants_full_list(client),
+            packet.clone(),
+        );
+        self.pending_song_list_pages.push(packet);
+
+        if done {
+            self.song_list_pages = std::mem::take(&mut self.pending_song_list_pages);
+
+            if let Some(song_list) = self.full_song_list() {
+                HUB.broadcast_filtered(
+                    |client| {
+                        !client.supports(SONG_LIST_PAGES_CAPABILITY) && wants_full_list(client)
+                    },
+                    song_list,
+                );
+            }
+        }
 
-        Ok(())
+        if let Some(delta) = delta {
+            info!(
+                "Song library revision {}: {} added, {} removed, {} changed",
+                delta.revision,
+                delta.added.len(),
+                delta.removed.len(),
+                delta.changed.len()
+            );
+            for packet in delta.packets() {
+                HUB.broadcast_filtered(
+                    |client| client.synced() && client.supports(SONG_LIST_DELTAS_CAPABILITY),
+                    packet,
+                );
+            }
+        }
     }
 
-    pub async fn write_packet(&mut self, packet: impl PartyPacket) -> anyhow::Result<()> {
-        let packet_type = packet.get_type();
-        let data = packet.encode_to_vec();
-
-        self.socket.write_all("moon".as_bytes()).await?;
-        self.socket.write_i32(packet_type as i32).await?;
-        self.socket.write_u64(data.len() as u64).await?;
-        self.socket.write_all(&data).await?;
+    /// The newest complete song list in one [`SongList`]
+    fn full_song_list(&self) -> Option<SongList> {
+        if self.song_list_pages.is_empty() {
+            return None;
+        }
 
-        Ok(())
+        let levels = page_levels(&self.song_list_pages).cloned().collect();
+        Some(SongList { levels })
     }
 
-    pub fn convert_practice(
-        practice_settings: &PracticeSettings,
-    ) -> quest_hook::libil2cpp::Result<Gc<PracticeSettings>> {
-        PracticeSettings::New_f32_f32_2(0.0, practice_settings._songSpeedMul / 10000.0)
-    }
+    /// Brings a newly connected panel up to date with the song list, then
+    /// lets it have the live updates. Panels taking deltas ask for the songs
+    /// they need with `SyncSongs` once there is a library to diff against.
+    pub fn sync_client(&self, client_id: ClientId) {
+        let library_known = self.library.revision() > 0;
+        if !(library_known && HUB.supports(client_id, SONG_LIST_DELTAS_CAPABILITY)) {
+            self.send_song_list(client_id);
+        }
 
-    pub fn convert_modifiers(
-        mods: &proto::items::GameplayModifiers,
-    ) -> quest_hook::libil2cpp::Result<Gc<GameplayModifiers>> {
-        GameplayModifiers::New_GameplayModifiers_EnergyType__cordl_bool__cordl_bool__cordl_bool_GameplayModifiers_EnabledObstacleType__cordl_bool__cordl_bool__cordl_bool__cordl_bool_GameplayModifiers_SongSpeed__cordl_bool__cordl_bool__cordl_bool__cordl_bool__cordl_bool1(
-            energy_type_from_i32(mods.energy_type),
-            mods.no_fail_on_0_energy,
-            mods.insta_fail,
-            mods.fail_on_saber_clash,
-            obstacle_type_from_i32(mods.enabled_obstacle_type),
-            mods.no_bombs,
-            false,
-            mods.strict_angles,
-            mods.disappearing_arrows,
-            song_speed_from_i32(mods.song_speed),
-            mods.no_bombs,
-            mods.ghost_notes,
-            mods.pro_mode,
-            mods.zen_mode,
-            mods.small_cubes,
-        )
+        HUB.mark_synced(client_id);
     }
 
-    pub async fn play_song(
-        &mut self,
-        beatmap_level: Gc<BeatmapLevel>,
-        characteristic: Gc<BeatmapCharacteristicSO>,
-        difficulty: BeatmapDifficulty,
-        packet: &PlaySong,
-    ) -> anyhow::Result<()> {
-        self.flow = Resources::FindObjectsOfTypeAll_1::<Gc<MainFlowCoordinator>>()?
-            .as_slice()
-            .first()
-            .map(|flow| flow._soloFreePlayFlowCoordinator);
-
-        let Some(flow) = self.flow else {
-            return Ok(());
-        };
-
-        extern "C" fn click_solo_button(_: *mut std::ffi::c_void) {
-            let mut solo_button = Resources::FindObjectsOfTypeAll_1::<Gc<NoTransitionsButton>>()
-                .unwrap()
-                .as_slice()
-                .iter()
-                .cloned()
-                .find(|x| {
-                    !x.is_null()
-                        && x.clone()
-                            .get_gameObject()
-                            .unwrap()
-                            .get_name()
-                            .unwrap()
-                            .to_string_lossy()
-                            == "SoloButton"
-                })
-                .expect("No solo button found");
-            solo_button.get_onClick().unwrap().Invoke().unwrap();
+    /// The whole song list, in whichever form the panel takes
+    fn send_song_list(&self, client_id: ClientId) {
+        if !HUB.supports(client_id, SONG_LIST_PAGES_CAPABILITY) {
+            if let Some(song_list) = self.full_song_list() {
+                HUB.send_to(client_id, song_list);
+            }
+            return;
         }
 
-        unsafe { party_panel_run_on_main_thread(click_solo_button, std::ptr::null_mut()) }
+        let mut packets = self.song_list_pages.clone();
 
-        let mut menu_scene_setup_data =
-            Resources::FindObjectsOfTypeAll_1::<Gc<MenuTransitionsHelper>>()?
-                .as_slice()
-                .first()
-                .copied()
-                .ok_or(anyhow!("No MenuTransitionsHelper found"))?;
+        // the rest of a list that is still converting gets broadcast to this
+        // panel, unless it only takes deltas
+        let library_known = self.library.revision() > 0;
+        if !(library_known && HUB.supports(client_id, SONG_LIST_DELTAS_CAPABILITY)) {
+            packets.extend(self.pending_song_list_pages.iter().cloned());
+        }
 
-        let key = BeatmapKey {
-            beatmapCharacteristic: characteristic,
-            difficulty,
-            levelId: beatmap_level.levelID,
-        };
-        let mut gameplay_setup_view_controller = flow._gameplaySetupViewController;
-        let environment_settings =
-            gameplay_setup_view_controller.get_environmentOverrideSettings()?;
-        let scheme = gameplay_setup_view_controller
-            .get_colorSchemesSettings()?
-            .GetSelectedColorScheme()?;
-        let settings = gameplay_setup_view_controller.get_playerSettings()?;
-
-        let modifiers = Self::convert_modifiers(packet.gameplay_modifiers.as_ref().unwrap())?;
-
-        menu_scene_setup_data.StartStandardLevel_OverrideEnvironmentSettings_ColorScheme__cordl_bool_ColorScheme_GameplayModifiers_PlayerSpecificSettings_PracticeSettings_EnvironmentsListModel_Il2CppString__cordl_bool_Action_Action_1_Action_2_Nullable_1_0(
-            Il2CppString::new("Solo"),
-            key,
-            beatmap_level,
-            environment_settings,
-            scheme,
-            false,
-            Gc::null(),
-            modifiers,
-            settings,
-            Gc::null(),
-            Gc::null(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Nullable_1::default() ,
-        )?;
-
-        // Action<IBeatmapLevel> SongLoaded = (loadedLevel) =>
-        // {
-        //     MenuTransitionsHelper _menuSceneSetupData = Resources.FindObjectsOfTypeAll<MenuTransitionsHelper>().First();
-        //     IDifficultyBeatmap diffbeatmap = loadedLevel.beatmapLevelData.GetDifficultyBeatmap(characteristic, difficulty);
-        //     GameplaySetupViewController gameplaySetupViewController = (GameplaySetupViewController)typeof(SinglePlayerLevelSelectionFlowCoordinator).GetField("_gameplaySetupViewController", BindingFlags.NonPublic | BindingFlags.Instance).GetValue(flow);
-        //     OverrideEnvironmentSettings environmentSettings = gameplaySetupViewController.environmentOverrideSettings;
-        //     ColorScheme scheme = gameplaySetupViewController.colorSchemesSettings.GetSelectedColorScheme();
-        //     PlayerSpecificSettings settings = gameplaySetupViewController.playerSettings;
-        //     //TODO: re add modifier customizability
-
-        //     GameplayModifiers modifiers = ConvertModifiers(packet.gameplayModifiers);
-        //     _menuSceneSetupData.StartStandardLevel(
-        //         "Solo",
-        //         diffbeatmap,
-        //         diffbeatmap.level,
-        //         environmentSettings,
-        //         scheme,
-        //         modifiers,
-        //         settings,
-        //         null,
-        //         "Menu",
-        //         false,
-        //         false,
-        //         null,
-        //         new Action<StandardLevelScenesTransitionSetupDataSO, LevelCompletionResults>((StandardLevelScenesTransitionSetupDataSO q, LevelCompletionResults r) => { }),
-        //         new Action<LevelScenesTransitionSetupDataSO, LevelCompletionResults>((LevelScenesTransitionSetupDataSO q, LevelCompletionResults r) => { })
-        //     );
-        // };
-        // HMMainThreadDispatcher.instance.Enqueue(() =>
-        // {
-        //     NoTransitionsButton button = Resources.FindObjectsOfTypeAll<NoTransitionsButton>().Where(x => x != null && x.gameObject.name == "SoloButton").FirstOrDefault();
-        //     button.onClick.Invoke();
-        // });
-        // if (true)
-        // {
-        //     var result = await GetLevelFromPreview(level);
-        //     if ( !(result?.isError == true))
-        //     {
-        //         SongLoaded(result?.beatmapLevel);
-        //         return;
-        //     }
-        // }
-        Ok(())
+        HUB.send_batch(client_id, packets);
     }
 
-    pub fn return_to_menu(&self) {
-        extern "C" fn return_to_main_menu_callback(_: *mut std::ffi::c_void) {
-            let Ok(controllers) =
-                Resources::FindObjectsOfTypeAll_1::<Gc<StandardLevelReturnToMenuController>>()
-            else {
-                return;
-            };
-            let Some(mut controller) = controllers.as_slice().first().cloned() else {
-                return;
-            };
-            let _ = controller.ReturnToMenu();
+    /// Answers [`SyncSongs`](crate::proto::packets::SyncSongs) with the deltas
+    /// the panel missed, or the whole list if they are no longer around
+    pub fn sync_songs(&self, client_id: ClientId, library_epoch: u64, since_revision: u64) {
+        match self.library.changes_since(library_epoch, since_revision) {
+            Some(deltas) => {
+                let packets = deltas.into_iter().flat_map(Delta::packets).collect();
+                HUB.send_batch(client_id, packets);
+            }
+            None => self.send_song_list(client_id),
         }
+    }
 
-        unsafe {
-            party_panel_run_on_main_thread(return_to_main_menu_callback, std::ptr::null_mut());
+    /// Applies a game command from a panel
+    pub async fn parse_packet(&mut self, packet: Packet) -> anyhow::Result<()> {
+        match packet {
+            Packet::PlaySong(playsong) => {
+                let playsong = v2::PlaySong::try_from(playsong).map_err(CommandError::from)?;
+                self.play_song(playsong).await?;
+            }
+            Packet::PlaySongV2(playsong) => self.play_song(playsong).await?,
+            Packet::Command(command) => {
+                let command_type = CommandType::try_from(command.command_type)
+                    .map_err(|e| CommandError::InvalidPacket(e.to_string()))?;
+                if let CommandType::ReturnToMenu = command_type {
+                    self.backend.return_to_menu();
+                }
+            }
+            Packet::DownloadSong(_) => {
+                return Err(CommandError::Unsupported(PacketType::DownloadSong).into());
+            }
+            packet => return Err(CommandError::Unsupported(packet.get_type()).into()),
         }
+
+        Ok(())
     }
 
-    // public static async Task<bool> HasDLCLevel(string levelId, AdditionalContentModel additionalContentModel = null)
-    // {
-    //     if(!levelId.StartsWith("custom_level_"))
-    //     {
-    //         Logger.Info(levelId);
-    //     }
-    //     additionalContentModel = additionalContentModel ?? Resources.FindObjectsOfTypeAll<AdditionalContentModel>().FirstOrDefault();
-
-    //     if (additionalContentModel != null)
-    //     {
-    //         getStatusCancellationTokenSource?.Cancel();
-    //         getStatusCancellationTokenSource = new CancellationTokenSource();
-
-    //         var token = getStatusCancellationTokenSource.Token;
-    //         return await additionalContentModel.GetLevelEntitlementStatusAsync(levelId, token) == AdditionalContentModel.EntitlementStatus.Owned;
-    //     }
-
-    //     return false;
-    // }
-    pub async fn has_dlc_level(
-        level_id: &str,
-        additional_content_model: Option<Gc<AdditionalContentModel>>,
-        token: Option<CancellationToken>,
-    ) -> anyhow::Result<bool> {
-        if !level_id.starts_with("custom_level_") {
-            info!("{}", level_id);
-        }
+    /// Starts a level for either version of `PlaySong`
+    async fn play_song(&mut self, playsong: v2::PlaySong) -> anyhow::Result<()> {
+        let desired_level = self
+            .songs
+            .iter()
+            .find(|x| x.hash.0 == playsong.level_id)
+            .ok_or_else(|| CommandError::LevelNotFound(playsong.level_id.clone()))?;
+
+        let characteristic_name = playsong
+            .characteristic
+            .as_ref()
+            .ok_or_else(|| CommandError::InvalidPacket("Missing characteristic".into()))?
+            .serialized_name()
+            .map_err(CommandError::from)?;
+
+        let difficulty = Difficulty::validate(playsong.difficulty).map_err(CommandError::from)?;
+        let modifiers = playsong
+            .gameplay_modifiers
+            .as_ref()
+            .ok_or_else(|| CommandError::InvalidPacket("Missing gameplay modifiers".into()))?;
+        let level = desired_level.level.clone();
+
+        self.backend
+            .start_level(level, characteristic_name, difficulty, modifiers)
+            .await
+    }
+}
 
-        let model = additional_content_model.or_else(|| {
-            Resources::FindObjectsOfTypeAll_1::<Gc<AdditionalContentModel>>()
-                .ok()
-                .and_then(|models| models.as_slice().first().cloned())
-        });
+/// A level as panels see it
+async fn preview<B: GameBackend>(
+    mut backend: B,
+    level: B::Level,
+) -> anyhow::Result<PreviewBeatmapLevel> {
+    let mut preview = backend.describe(&level)?;
+    preview.favorited = backend.is_favorite(&preview.level_id)?;
+    preview.owned = backend.is_owned(&preview.level_id).await?;
+
+    if !preview.owned {
+        preview.owned_justification = "Unowned DLC Level".to_string();
+    }
 
-        let Some(mut model) = model else {
-            return Ok(false);
-        };
+    Ok(preview)
+}
+
+/// Every level in a paged song list, in order
+fn page_levels(pages: &[Packet]) -> impl Iterator<Item = &PreviewBeatmapLevel> {
+    pages
+        .iter()
+        .filter_map(|packet| match packet {
+            Packet::SongListPage(page) => Some(page.levels.iter()),
+            _ => None,
+        })
+        .flatten()
+}
 
-        let status = model
-            .IAdditionalContentEntitlementModel_GetLevelEntitlementStatusAsync(
-                Il2CppString::new(level_id),
-                token.unwrap_or_default(),
-            )?
-            .into_awaitable()
-            .await?;
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use tokio::{sync::Mutex, time::timeout};
+
+    use super::*;
+    use crate::{
+        backend::fake::{FakeBackend, FakeLevel},
+        hub::Outbound,
+        proto::{
+            items::{Characteristic, GameplayModifiers},
+            packets::{ack::ErrorCode, Ack, PlaySong, SyncSongs},
+        },
+    };
+
+    /// Tests share the global hub, and broadcasts would reach each other's panels
+    static HUB_LOCK: Mutex<()> = Mutex::const_new(());
+
+    fn level(n: usize) -> FakeLevel {
+        FakeLevel {
+            preview: PreviewBeatmapLevel {
+                level_id: format!("level{n}"),
+                name: format!("Level {n}"),
+                duration: "2:30".to_string(),
+                chars: vec![Characteristic {
+                    name: "Standard".to_string(),
+                    diffs: vec!["Expert".to_string()],
+                }],
+                ..Default::default()
+            },
+            favorite: n.is_multiple_of(3),
+            owned: !n.is_multiple_of(5),
+        }
+    }
 
-        Ok(status == EntitlementStatus::Owned)
+    struct Panel {
+        id: ClientId,
+        receiver: mpsc::Receiver<Outbound>,
+        /// The rest of a batch
+        batch: VecDeque<Packet>,
     }
 
-    // public static async Task<BeatmapLevelsModel.GetBeatmapLevelResult?> GetLevelFromPreview(IPreviewBeatmapLevel level, BeatmapLevelsModel beatmapLevelsModel = null)
-    // {
-    //     beatmapLevelsModel = beatmapLevelsModel ?? Resources.FindObjectsOfTypeAll<BeatmapLevelsModel>().FirstOrDefault();
-
-    //     if (beatmapLevelsModel != null)
-    //     {
-    //         getLevelCancellationTokenSource?.Cancel();
-    //         getLevelCancellationTokenSource = new CancellationTokenSource();
-
-    //         var token = getLevelCancellationTokenSource.Token;
-
-    //         BeatmapLevelsModel.GetBeatmapLevelResult? result = null;
-    //         try
-    //         {
-    //             result = await beatmapLevelsModel.GetBeatmapLevelAsync(level.levelID, token);
-    //         }
-    //         catch (OperationCanceledException) { }
-    //         if (result?.isError == true || result?.beatmapLevel == null)
-    //         {
-    //             Logger.Error("Failed to load Level");
-    //             return null; //Null out entirely in case of error
-    //         }
-    //         return result;
-    //     }
-    //     return null;
-    // }
-    pub fn get_level_from_preview(
-        &mut self,
-        level: &PreviewBeatmapLevel,
-    ) -> anyhow::Result<Option<Gc<BeatmapLevel>>> {
-        let beatmap_levels_model = Resources::FindObjectsOfTypeAll_1::<Gc<BeatmapLevelsModel>>()?
-            .as_slice()
-            .first()
-            .cloned();
-
-        let Some(mut beatmap_levels_model) = beatmap_levels_model else {
-            return Ok(None);
-        };
+    impl Panel {
+        /// Joins the hub without being caught up yet
+        fn register(capabilities: &[&str]) -> Self {
+            let capabilities = capabilities.iter().map(ToString::to_string).collect();
+            let (id, receiver) = HUB.register("test".to_string(), capabilities, None);
+            Self {
+                id,
+                receiver,
+                batch: VecDeque::new(),
+            }
+        }
 
-        let result = beatmap_levels_model.GetBeatmapLevel(Il2CppString::new(&level.level_id));
+        /// Joins the hub and asks to be caught up, like a session does
+        fn connect(context: &WebContextHandle, capabilities: &[&str]) -> Self {
+            let panel = Self::register(capabilities);
+            context.send(WebContextMessage::SyncClient(panel.id));
+            panel
+        }
 
-        match result {
-            Ok(level) => Ok(Some(level)),
-            _ => {
-                info!("Failed to load Level");
-                Ok(None)
+        async fn recv(&mut self) -> Packet {
+            if let Some(packet) = self.batch.pop_front() {
+                return packet;
+            }
+
+            let outbound = timeout(Duration::from_secs(5), self.receiver.recv())
+                .await
+                .expect("no packet in time")
+                .expect("panel was disconnected");
+            match outbound {
+                Outbound::Packet(packet) => *packet,
+                Outbound::Batch(packets) => {
+                    self.batch = packets.into();
+                    self.batch.pop_front().expect("empty batch")
+                }
             }
         }
-    }
 
-    //     public static async Task<PreviewBeatmapLevel> ConvertToPacketType(IPreviewBeatmapLevel x, PlayerData playerData)
-    // {
-    //     //Make packet level
-    //     var level = new PreviewBeatmapLevel();
-    //     try
-    //     {
-    //         //Set Parameters;
-    //         level.LevelId = x.levelID;
-    //         level.Name = x.songName;
-    //         level.SubName = x.songSubName;
-    //         level.Author = x.songAuthorName;
-    //         level.Mapper = x.levelAuthorName;
-    //         level.BPM = x.beatsPerMinute;
-    //         level.Duration = TimeExtensions.MinSecDurationText(x.songDuration);
-    //         level.Favorited = playerData.favoritesLevelIds.Contains(x.levelID);
-    //         level.Owned = await SaberUtilities.HasDLCLevel(x.levelID);
-    //         if(!level.Owned)
-    //         {
-    //             level.OwnedJustificaton = "Unowned DLC Level";
-    //         }
-    //         if(x is CustomPreviewBeatmapLevel)
-    //         {
-    //             var extras = Collections.RetrieveExtraSongData(new string(x.levelID.Skip(13).ToArray()));
-    //             var requirements = extras?._difficulties.SelectMany((x) => { return x.additionalDifficultyData._requirements; });
-    //             List<string> missingReqs = new List<string>();
-    //             if (
-    //                 (requirements?.Count() > 0) &&
-    //                 (!requirements?.ToList().All(x => {
-    //                     if(Collections.capabilities.Contains(x))
-    //                     {
-    //                         return true;
-    //                     }
-    //                     else
-    //                     {
-    //                         missingReqs.Add(x);
-    //                         return false;
-    //                     }
-    //                 }) ?? false)
-    //             )
-    //             {
-    //                 level.Owned = false;
-    //                 level.OwnedJustificaton = "Missing " + missingReqs.Aggregate((x, x2) => { return x + x2; });
-    //             }
-    //         }
-
-    //         OverrideLabels labels = new OverrideLabels();
-    //         var songData = Collections.RetrieveExtraSongData(new string(x.levelID.Skip(13).ToArray()));
-
-    //         Dictionary<string, OverrideLabels> LevelLabels = new Dictionary<string, OverrideLabels>();
-    //         LevelLabels.Clear();
-    //         if (songData != null)
-    //         {
-    //             foreach (SongCore.Data.ExtraSongData.DifficultyData diffLevel in songData._difficulties)
-    //             {
-
-    //                 var difficulty = diffLevel._difficulty;
-    //                 string characteristic = diffLevel._beatmapCharacteristicName;
-
-    //                 if (!LevelLabels.ContainsKey(characteristic))
-    //                 {
-    //                     LevelLabels.Add(characteristic, new OverrideLabels());
-    //                 }
-
-    //                 var charLabels = LevelLabels[characteristic];
-    //                 if (!string.IsNullOrWhiteSpace(diffLevel._difficultyLabel))
-    //                 {
-
-    //                     switch (difficulty)
-    //                     {
-    //                         case BeatmapDifficulty.Easy:
-    //                             charLabels.EasyOverride = diffLevel._difficultyLabel;
-    //                             break;
-    //                         case BeatmapDifficulty.Normal:
-    //                             charLabels.NormalOverride = diffLevel._difficultyLabel;
-    //                             break;
-    //                         case BeatmapDifficulty.Hard:
-    //                             charLabels.HardOverride = diffLevel._difficultyLabel;
-    //                             break;
-    //                         case BeatmapDifficulty.Expert:
-    //                             charLabels.ExpertOverride = diffLevel._difficultyLabel;
-    //                             break;
-    //                         case BeatmapDifficulty.ExpertPlus:
-    //                             charLabels.ExpertPlusOverride = diffLevel._difficultyLabel;
-    //                             break;
-    //                     }
-    //                 }
-    //             }
-    //         }
-    //         level.chars = x.previewDifficultyBeatmapSets.Select((PreviewDifficultyBeatmapSet set) => { Characteristic Char = new Characteristic(); Char.Name = set.beatmapCharacteristic.serializedName;    Char.diffs = set.beatmapDifficulties.Select((BeatmapDifficulty diff)=> { return Name(LevelLabels.ContainsKey(Char.Name) ? LevelLabels[Char.Name]: null, diff); }).ToArray(); return Char; }).ToArray();
-    //         if (x.GetType().Name.Contains("BeatmapLevelSO"))
-    //         {
-    //             Texture2D tex;
-    //             Sprite sprite = (await x.GetCoverImageAsync(System.Threading.CancellationToken.None));
-    //             try
-    //             {
-    //                 tex = sprite.texture;
-    //             }
-    //             catch
-    //             {
-    //                 tex = GetFromUnreadable((x as CustomPreviewBeatmapLevel)?.defaultCoverImage.texture, sprite.textureRect);
-    //             }
-    //             if (!(x is CustomPreviewBeatmapLevel) || tex == null || !tex.isReadable)
-    //             {
-    //                 tex = GetFromUnreadable(tex, InvertAtlas(sprite.textureRect));
-    //             }
-    //             level.cover = tex.EncodeToJPG();
-    //         }
-    //         else
-    //         {
-    //             if(x is CustomPreviewBeatmapLevel)
-    //             {
-    //                 string path = Path.Combine(((CustomPreviewBeatmapLevel)x).customLevelPath, ((CustomPreviewBeatmapLevel)x).standardLevelInfoSaveData.coverImageFilename);
-    //                 if (File.Exists(path))
-    //                 {
-    //                     level.coverPath = path;
-    //                 }
-    //             }
-    //         }
-    //     }
-    //     catch(Exception e)
-    //     {
-    //         Logger.Error(e.ToString());
-    //     }
-    //     return level;
-    // }
-    pub async fn convert_to_packet_type(
-        mut x: Gc<BeatmapLevel>,
-        mut player_data: Gc<PlayerData>,
-        token: Option<CancellationToken>,
-    ) -> anyhow::Result<PreviewBeatmapLevel> {
-        fn format_duration(duration: f32) -> String {
-            if duration.is_nan() {
-                return String::new();
+        /// Skips everything up to the next packet `pick` accepts
+        async fn recv_matching<T>(&mut self, pick: impl Fn(Packet) -> Option<T>) -> T {
+            loop {
+                if let Some(found) = pick(self.recv().await) {
+                    return found;
+                }
             }
-            let minutes = (duration / 60.0) as i32;
-            let seconds = (duration % 60.0) as i32;
-            format!("{}:{:02}", minutes, seconds)
         }
 
-        let mut level = PreviewBeatmapLevel {
-            level_id: x.levelID.to_string_lossy(),
-            name: x.songName.to_string_lossy().to_string(),
-            sub_name: x.songSubName.to_string_lossy().to_string(),
-            author: x.songAuthorName.to_string_lossy().to_string(),
-            mapper: x
-                .allMappers
-                .as_slice()
-                .iter()
-                .map(|m| m.to_string_lossy())
-                .join(","),
-            bpm: x.beatsPerMinute,
-            duration: format_duration(x.songDuration),
-            favorited: player_data.get_favoritesLevelIds()?.Contains(x.levelID)?,
-            ..Default::default()
-        };
-        level.owned = Self::has_dlc_level(&level.level_id, None, token).await?;
+        async fn ack(&mut self) -> Ack {
+            self.recv_matching(|packet| match packet {
+                Packet::Ack(ack) => Some(ack),
+                _ => None,
+            })
+            .await
+        }
 
-        if !level.owned {
-            level.owned_justification = "Unowned DLC Level".to_string();
+        /// Begin, every page and end of the next paged song list
+        async fn song_list(&mut self) -> Vec<Packet> {
+            let mut packets = vec![
+                self.recv_matching(|packet| {
+                    matches!(packet, Packet::SongListBegin(_)).then_some(packet)
+                })
+                .await,
+            ];
+            while !matches!(packets.last(), Some(Packet::SongListEnd(_))) {
+                packets.push(self.recv().await);
+            }
+            packets
         }
-        level.chars = System::Linq::Enumerable::ToList(x.GetBeatmapKeys()?)?
-            ._items
-            .as_slice()
-            .iter()
-            .filter(|i| **i != BeatmapKey::default())
-            .map(|i| (i.beatmapCharacteristic, i.difficulty))
-            .chunk_by(|(characteristic, _)| *characteristic)
-            .into_iter()
-            .map(
-                |(characteristic, difficulties)| -> quest_hook::libil2cpp::Result<_> {
-                    let char = proto::items::Characteristic {
-                        name: characteristic
-                            .clone()
-                            .get_serializedName()?
-                            .to_string_lossy()
-                            .to_string(),
-                        diffs: difficulties
-                            .map(|(_, diff)| difficulty_name(diff))
-                            .collect::<Vec<_>>(),
-                    };
-                    Ok(char)
-                },
-            )
-            .try_collect()?;
 
-        // Skip cover image handling for now as it requires more complex Unity texture manipulation
+        fn send(&self, context: &WebContextHandle, packet: impl Into<Packet>) {
+            context.send(WebContextMessage::Packet {
+                client_id: self.id,
+                packet: Box::new(packet.into()),
+            });
+        }
+    }
+
+    impl Drop for Panel {
+        fn drop(&mut self) {
+            HUB.unregister(self.id);
+        }
+    }
 
-        Ok(level)
+    fn play_song(level_id: &str, request_id: u32) -> PlaySong {
+        PlaySong {
+            level_id: level_id.to_string(),
+            difficulty: "Expert".to_string(),
+            characteristic: Some(Characteristic {
+                name: "Standard".to_string(),
+                diffs: Vec::new(),
+            }),
+            gameplay_modifiers: Some(GameplayModifiers::default()),
+            request_id,
+        }
     }
-}
 
-fn energy_type_from_i32(value: i32) -> GameplayModifiers_EnergyType {
-    match value {
-        0 => GameplayModifiers_EnergyType::Bar,
-        1 => GameplayModifiers_EnergyType::Battery,
-        _ => GameplayModifiers_EnergyType::Bar,
+    fn sync_songs(library_epoch: u64, since_revision: u64, request_id: u32) -> SyncSongs {
+        SyncSongs {
+            since_revision,
+            request_id,
+            library_epoch,
+        }
     }
-}
 
-fn obstacle_type_from_i32(value: i32) -> GameplayModifiers_EnabledObstacleType {
-    match value {
-        0 => GameplayModifiers_EnabledObstacleType::All,
-        1 => GameplayModifiers_EnabledObstacleType::FullHeightOnly,
-        2 => GameplayModifiers_EnabledObstacleType::NoObstacles,
-        _ => GameplayModifiers_EnabledObstacleType::All,
+    #[tokio::test]
+    async fn song_list_is_paged() {
+        let _hub = HUB_LOCK.lock().await;
+        let backend = FakeBackend::new((0..450).map(level).collect());
+        let context = WebContext::new(backend).spawn();
+        let mut pages = Panel::connect(&context, &[SONG_LIST_PAGES_CAPABILITY]);
+        let mut legacy = Panel::connect(&context, &[]);
+
+        context.send(WebContextMessage::SongsLoaded);
+
+        let list = pages.song_list().await;
+        let Packet::SongListBegin(begin) = &list[0] else {
+            unreachable!()
+        };
+        assert_eq!(begin.total_levels, 450);
+        assert_eq!(begin.page_size, SONG_LIST_PAGE_SIZE as u32);
+
+        let page_sizes = list[1..list.len() - 1]
+            .iter()
+            .map(|packet| match packet {
+                Packet::SongListPage(page) => page.levels.len(),
+                packet => panic!("expected a page, got {packet:?}"),
+            })
+            .collect_vec();
+        assert_eq!(page_sizes, [200, 200, 50]);
+
+        let Some(Packet::SongListEnd(end)) = list.last() else {
+            unreachable!()
+        };
+        assert_eq!((end.pages, end.levels, end.failed_levels), (3, 450, 0));
+        assert_eq!(end.revision, 1);
+
+        let levels = page_levels(&list).collect_vec();
+        assert_eq!(levels[3].level_id, "level3");
+        assert!(levels[3].favorited && levels[3].owned);
+        assert_eq!(levels[5].level_id, "level5");
+        assert!(!levels[5].favorited && !levels[5].owned);
+        assert_eq!(levels[5].owned_justification, "Unowned DLC Level");
+
+        let song_list = legacy
+            .recv_matching(|packet| match packet {
+                Packet::SongList(song_list) => Some(song_list),
+                _ => None,
+            })
+            .await;
+        assert_eq!(song_list.levels.len(), 450);
+        assert_eq!(song_list.levels, levels.into_iter().cloned().collect_vec());
+
+        // panels connecting later get the same list replayed
+        let mut late = Panel::connect(&context, &[SONG_LIST_PAGES_CAPABILITY]);
+        assert_eq!(late.song_list().await, list);
     }
-}
 
-fn song_speed_from_i32(value: i32) -> GameplayModifiers_SongSpeed {
-    match value {
-        0 => GameplayModifiers_SongSpeed::Normal,
-        1 => GameplayModifiers_SongSpeed::Faster,
-        2 => GameplayModifiers_SongSpeed::Slower,
-        3 => GameplayModifiers_SongSpeed::SuperFast,
-        _ => GameplayModifiers_SongSpeed::Normal,
+    #[tokio::test]
+    async fn panels_get_pages_once_after_syncing() {
+        let _hub = HUB_LOCK.lock().await;
+        let backend = FakeBackend::new((0..450).map(level).collect());
+        let context = WebContext::new(backend).spawn();
+        let mut early = Panel::connect(&context, &[SONG_LIST_PAGES_CAPABILITY]);
+
+        // in the hub while the list converts, but its sync isn't handled yet
+        let mut joining = Panel::register(&[SONG_LIST_PAGES_CAPABILITY]);
+        context.send(WebContextMessage::SongsLoaded);
+        let list = early.song_list().await;
+        assert!(joining.receiver.try_recv().is_err());
+
+        context.send(WebContextMessage::SyncClient(joining.id));
+        assert_eq!(joining.song_list().await, list);
+
+        // nothing left over from the broadcasts
+        let ack_only = play_song("missing", 1);
+        joining.send(&context, ack_only);
+        assert!(matches!(joining.recv().await, Packet::Ack(_)));
     }
-}
 
-fn difficulty_name(difficulty: BeatmapDifficulty) -> String {
-    match difficulty {
-        BeatmapDifficulty::Easy => "Easy".to_string(),
-        BeatmapDifficulty::Normal => "Normal".to_string(),
-        BeatmapDifficulty::Hard => "Hard".to_string(),
-        BeatmapDifficulty::Expert => "Expert".to_string(),
-        BeatmapDifficulty::ExpertPlus => "ExpertPlus".to_string(),
-        _ => "Unknown".to_string(),
+    #[tokio::test]
+    async fn play_song_errors() {
+        let _hub = HUB_LOCK.lock().await;
+        let backend = FakeBackend::new((0..3).map(level).collect());
+        let context = WebContext::new(backend.clone()).spawn();
+        let mut panel = Panel::connect(&context, &[]);
+
+        context.send(WebContextMessage::SongsLoaded);
+
+        let cases = [
+            (play_song("missing", 1), ErrorCode::LevelNotFound),
+            (
+                PlaySong {
+                    characteristic: Some(Characteristic {
+                        name: "OneSaber".to_string(),
+                        diffs: Vec::new(),
+                    }),
+                    ..play_song("level1", 2)
+                },
+                ErrorCode::CharacteristicNotFound,
+            ),
+            (
+                PlaySong {
+                    characteristic: None,
+                    ..play_song("level1", 3)
+                },
+                ErrorCode::InvalidPacket,
+            ),
+            (
+                PlaySong {
+                    difficulty: "Impossible".to_string(),
+                    ..play_song("level1", 4)
+                },
+                ErrorCode::InvalidPacket,
+            ),
+            (
+                PlaySong {
+                    gameplay_modifiers: None,
+                    ..play_song("level1", 5)
+                },
+                ErrorCode::InvalidPacket,
+            ),
+        ];
+        for (packet, code) in cases {
+            let request_id = packet.request_id;
+            panel.send(&context, packet);
+
+            let ack = panel.ack().await;
+            assert_eq!(ack.request_id, request_id);
+            assert!(!ack.success);
+            assert_eq!(ack.error_code(), code, "{}", ack.message);
+        }
+        assert_eq!(backend.game().playing, None);
+
+        panel.send(&context, play_song("level1", 6));
+        let ack = panel.ack().await;
+        assert!(ack.success, "{}", ack.message);
+        assert_eq!(
+            backend.game().playing.as_ref().map(|started| (
+                started.level_id.as_str(),
+                started.characteristic.as_str(),
+                started.difficulty
+            )),
+            Some(("level1", "Standard", Difficulty::Expert))
+        );
+        assert_eq!(backend.game().score.map(|score| score.length), Some(150.0));
+
+        panel.send(&context, play_song("level2", 7));
+        let ack = panel.ack().await;
+        assert_eq!(ack.error_code(), ErrorCode::NotInMenu);
+
+        // without a request_id there is no Ack, the panel gets an ErrorEvent instead
+        panel.send(&context, play_song("level2", 0));
+        let event = panel
+            .recv_matching(|packet| match packet {
+                Packet::ErrorEvent(event) => Some(event),
+                _ => None,
+            })
+            .await;
+        assert_eq!(event.source(), Source::Command);
+        assert_eq!(event.severity(), Severity::Error);
+        assert_eq!(backend.game().playing.as_ref().unwrap().level_id, "level1");
+
+        // the typed PlaySong goes the same way
+        backend.return_to_menu();
+        let song = v2::PlaySong {
+            level_id: "level2".to_string(),
+            difficulty: Difficulty::ExpertPlus as i32,
+            characteristic: Some(v2::Characteristic::from_serialized_name("Standard")),
+            gameplay_modifiers: Some(GameplayModifiers::default()),
+            request_id: 8,
+        };
+        panel.send(&context, song);
+        let ack = panel.ack().await;
+        assert!(ack.success, "{}", ack.message);
+        assert_eq!(
+            backend
+                .game()
+                .playing
+                .as_ref()
+                .map(|started| (started.level_id.as_str(), started.difficulty)),
+            Some(("level2", Difficulty::ExpertPlus))
+        );
     }
-}
 
-fn difficulty_from_name(name: &str) -> BeatmapDifficulty {
-    match name {
-        "Easy" => BeatmapDifficulty::Easy,
-        "Normal" => BeatmapDifficulty::Normal,
-        "Hard" => BeatmapDifficulty::Hard,
-        "Expert" => BeatmapDifficulty::Expert,
-        "ExpertPlus" => BeatmapDifficulty::ExpertPlus,
-        _ => BeatmapDifficulty::default(),
+    #[tokio::test]
+    async fn sync_songs_sends_deltas() {
+        let _hub = HUB_LOCK.lock().await;
+        let backend = FakeBackend::new((0..4).map(level).collect());
+        let capabilities = [SONG_LIST_PAGES_CAPABILITY, SONG_LIST_DELTAS_CAPABILITY];
+        let context = WebContext::new(backend.clone()).spawn();
+        let mut panel = Panel::connect(&context, &capabilities);
+
+        context.send(WebContextMessage::SongsLoaded);
+        let first_list = panel.song_list().await;
+        let Some(Packet::SongListEnd(end)) = first_list.last() else {
+            unreachable!()
+        };
+        let epoch = end.library_epoch;
+        assert_ne!(epoch, 0);
+
+        {
+            let mut game = backend.game();
+            game.levels.remove(0);
+            game.levels[0].preview.name = "Renamed".to_string();
+            game.levels.push(level(4));
+        }
+        context.send(WebContextMessage::SongsLoaded);
+
+        // live panels taking deltas only get what changed
+        let mut deltas = Vec::new();
+        while !matches!(deltas.last(), Some(Packet::SongsAdded(_))) {
+            match panel.recv().await {
+                packet @ (Packet::SongsRemoved(_)
+                | Packet::SongsChanged(_)
+                | Packet::SongsAdded(_)) => deltas.push(packet),
+                Packet::SongListBegin(_) | Packet::SongListPage(_) | Packet::SongListEnd(_) => {
+                    panic!("got a song list after the first")
+                }
+                _ => {}
+            }
+        }
+        let [Packet::SongsRemoved(removed), Packet::SongsChanged(changed), Packet::SongsAdded(added)] =
+            &deltas[..]
+        else {
+            panic!("unexpected deltas {deltas:?}");
+        };
+        assert_eq!((removed.previous_revision, removed.revision), (1, 2));
+        assert_eq!(removed.level_ids, ["level0"]);
+        assert_eq!(changed.levels.len(), 1);
+        assert_eq!(changed.levels[0].name, "Renamed");
+        assert_eq!(added.levels.len(), 1);
+        assert_eq!(added.levels[0].level_id, "level4");
+
+        // a panel that reconnects at revision 1 gets the same deltas, the Ack
+        // shows nothing else follows
+        let mut reconnected = Panel::connect(&context, &capabilities);
+        reconnected.send(&context, sync_songs(epoch, 1, 1));
+        let mut synced = Vec::new();
+        loop {
+            match reconnected.recv().await {
+                Packet::Ack(ack) => {
+                    assert!(ack.success);
+                    break;
+                }
+                packet => synced.push(packet),
+            }
+        }
+        assert_eq!(synced, deltas);
+
+        // up to date, nothing to send
+        reconnected.send(&context, sync_songs(epoch, 2, 2));
+        assert!(matches!(reconnected.recv().await, Packet::Ack(_)));
+
+        // unknown revisions, revisions from before the mod restarted and
+        // panels without a list all get the whole list
+        for (request_id, (library_epoch, since_revision)) in
+            [(epoch, 7), (epoch ^ 1, 2), (0, 0)].into_iter().enumerate()
+        {
+            reconnected.send(
+                &context,
+                sync_songs(library_epoch, since_revision, request_id as u32 + 3),
+            );
+            let list = reconnected.song_list().await;
+            assert_ne!(list, first_list);
+            assert_eq!(page_levels(&list).count(), 4);
+            assert!(matches!(reconnected.recv().await, Packet::Ack(_)));
+        }
     }
 }